@@ -1,15 +1,478 @@
 use crate::core;
+use crate::core::chain::{Chains, OutboundChain};
+use crate::core::check::{ConfigCheck, DanglingTag};
+use crate::core::config::{IConfig, UserConfigValue};
+use crate::core::dns::{Dns, DnsSettings};
+use crate::core::domain_list::{DomainList, DomainLists, ImportResult, ListFormat};
+use crate::core::geodata::{
+    GeoCategory, GeoData, GeoDomain, GeoSiteMatch, GEOIP_FILE, GEOSITE_FILE,
+};
+use crate::core::loaded::{LoadedOutbound, LoadedOutbounds};
+use crate::core::options::OutboundOptions;
+use crate::core::outbound::{OutboundEntry, OutboundMeta, Outbounds};
+use crate::core::profile::Profile;
+use crate::core::reverse::{ReverseBridge, ReverseConfig, ReverseProxies};
+use crate::core::routing::Routings;
+use crate::core::schema::RuleObject;
+use crate::core::server::{NewNode, ServerPair, Servers, X25519Pair};
+use crate::core::simulate::{RouteQuery, RouteResult, RouteSimulator};
+use crate::core::template::{BlockPosition, RoutingBlock, RoutingTemplates};
+use crate::core::tray::Tray;
+use crate::core::variable::Variables;
+use crate::wrap_err;
 use anyhow::Context;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tauri::AppHandle;
+
+type CmdResult<T = ()> = Result<T, String>;
+
+/* 耗时的工作放到阻塞线程里, 不卡住界面 */
+async fn run_blocking<T, F>(f: F) -> anyhow::Result<T>
+where
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(f).await?
+}
 
 #[tauri::command]
 pub fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+/* 当前配置 */
+#[tauri::command]
+pub fn get_user_config(app_handle: AppHandle) -> CmdResult<UserConfigValue> {
+    wrap_err!(IConfig::snapshot(&app_handle).ok_or(anyhow::anyhow!("failed to get config")))
+}
+
+/* 配置方案 */
+#[tauri::command]
+pub fn list_profiles(app_handle: AppHandle) -> CmdResult<Vec<Profile>> {
+    Ok(Profile::list(&app_handle))
+}
+
+#[tauri::command]
+pub fn save_profile(app_handle: AppHandle, name: String) -> CmdResult {
+    wrap_err!(Profile::save(&app_handle, name))
+}
+
+#[tauri::command]
+pub fn delete_profile(app_handle: AppHandle, name: String) -> CmdResult {
+    wrap_err!(Profile::delete(&app_handle, &name))
+}
+
+#[tauri::command]
+pub fn apply_profile(app_handle: AppHandle, name: String) -> CmdResult {
+    wrap_err!(Profile::apply(&app_handle, &name))
+}
+
+/* outbound 列表 */
+#[tauri::command]
+pub fn list_outbounds() -> CmdResult<Vec<OutboundEntry>> {
+    Ok(IConfig::get_outbound_list().unwrap_or_default())
+}
+
+/* 修改 outbound 元数据, id 为相对 outbound 目录的路径, 选项用 set_outbound_options 修改 */
+#[tauri::command]
+pub fn set_outbound_meta(app_handle: AppHandle, id: String, meta: OutboundMeta) -> CmdResult {
+    wrap_err!(IConfig::get_outbound_list()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|outbound| outbound.id == id)
+        .ok_or(anyhow::anyhow!("outbound {} not found", id))
+        .and_then(|outbound| {
+            let options = OutboundEntry::read_meta(&outbound.path)?.options;
+            OutboundEntry::write_meta(&outbound.path, &OutboundMeta { options, ..meta })
+        })
+        .and_then(|_| Tray::update_tray(&app_handle)))
+}
+
+/* outbound 选项, 暂存时覆盖, 不改 outbound 文件 */
+#[tauri::command]
+pub fn get_outbound_options(id: String) -> CmdResult<OutboundOptions> {
+    wrap_err!(Outbounds::resolve(&id)
+        .and_then(|path| OutboundEntry::read_meta(&path))
+        .map(|meta| meta.options))
+}
+
+#[tauri::command]
+pub fn set_outbound_options(
+    app_handle: AppHandle,
+    id: String,
+    options: OutboundOptions,
+) -> CmdResult {
+    wrap_err!(Outbounds::set_options(&app_handle, &id, options))
+}
+
+/* outbound 文件增删改, 写入前按 xray 结构校验 */
+#[tauri::command]
+pub fn read_outbound(id: String) -> CmdResult<Value> {
+    wrap_err!(Outbounds::read(&id))
+}
+
+#[tauri::command]
+pub fn create_outbound(app_handle: AppHandle, id: String, content: Value) -> CmdResult {
+    wrap_err!(
+        Outbounds::create(&app_handle, &id, &content).and_then(|_| Tray::update_tray(&app_handle))
+    )
+}
+
+#[tauri::command]
+pub fn replace_outbound(app_handle: AppHandle, id: String, content: Value) -> CmdResult {
+    wrap_err!(
+        Outbounds::replace(&app_handle, &id, &content).and_then(|_| Tray::update_tray(&app_handle))
+    )
+}
+
+#[tauri::command]
+pub fn rename_outbound(app_handle: AppHandle, id: String, new_id: String) -> CmdResult {
+    wrap_err!(
+        Outbounds::rename(&app_handle, &id, &new_id).and_then(|_| Tray::update_tray(&app_handle))
+    )
+}
+
+#[tauri::command]
+pub fn duplicate_outbound(app_handle: AppHandle, id: String, new_id: String) -> CmdResult {
+    wrap_err!(Outbounds::duplicate(&id, &new_id).and_then(|_| Tray::update_tray(&app_handle)))
+}
+
+#[tauri::command]
+pub fn delete_outbound(app_handle: AppHandle, id: String) -> CmdResult {
+    wrap_err!(Outbounds::delete(&app_handle, &id).and_then(|_| Tray::update_tray(&app_handle)))
+}
+
+/* 路由文件增删改 */
+#[tauri::command]
+pub fn create_routing(app_handle: AppHandle, name: String, content: Option<Value>) -> CmdResult {
+    wrap_err!(
+        Routings::create(&app_handle, &name, content).and_then(|_| Tray::update_tray(&app_handle))
+    )
+}
+
+#[tauri::command]
+pub fn rename_routing(app_handle: AppHandle, name: String, new_name: String) -> CmdResult {
+    wrap_err!(Routings::rename(&app_handle, &name, &new_name)
+        .and_then(|_| Tray::update_tray(&app_handle)))
+}
+
+#[tauri::command]
+pub fn delete_routing(app_handle: AppHandle, name: String) -> CmdResult {
+    wrap_err!(Routings::delete(&app_handle, &name).and_then(|_| Tray::update_tray(&app_handle)))
+}
+
+/* 路由规则, index 为规则在文件里的下标 */
+#[tauri::command]
+pub fn list_routing_rules(app_handle: AppHandle, name: String) -> CmdResult<Vec<RuleObject>> {
+    wrap_err!(Routings::list_rules(&app_handle, &name))
+}
+
+#[tauri::command]
+pub fn insert_routing_rule(
+    app_handle: AppHandle,
+    name: String,
+    index: Option<usize>,
+    rule: RuleObject,
+) -> CmdResult {
+    wrap_err!(Routings::insert_rule(&app_handle, &name, index, rule))
+}
+
+#[tauri::command]
+pub fn update_routing_rule(
+    app_handle: AppHandle,
+    name: String,
+    index: usize,
+    rule: RuleObject,
+) -> CmdResult {
+    wrap_err!(Routings::update_rule(&app_handle, &name, index, rule))
+}
+
+#[tauri::command]
+pub fn move_routing_rule(app_handle: AppHandle, name: String, from: usize, to: usize) -> CmdResult {
+    wrap_err!(Routings::move_rule(&app_handle, &name, from, to))
+}
+
+#[tauri::command]
+pub fn set_routing_rule_enabled(
+    app_handle: AppHandle,
+    name: String,
+    index: usize,
+    enabled: bool,
+) -> CmdResult {
+    wrap_err!(Routings::set_rule_enabled(
+        &app_handle,
+        &name,
+        index,
+        enabled
+    ))
+}
+
+#[tauri::command]
+pub fn delete_routing_rule(app_handle: AppHandle, name: String, index: usize) -> CmdResult {
+    wrap_err!(Routings::delete_rule(&app_handle, &name, index))
+}
+
+/* 检查当前路由引用的 tag, 需要先暂存过配置 */
+#[tauri::command]
+pub fn check_active_routing(app_handle: AppHandle) -> CmdResult<Vec<DanglingTag>> {
+    wrap_err!(
+        core::path::AppPath::xray_temp_config_dir().and_then(|confdir| {
+            let routing = IConfig::active_routing(&app_handle).unwrap_or_default();
+            let routing_path = core::path::AppPath::xray_routing_dir()?.join(routing);
+            ConfigCheck::check_routing(&app_handle, &confdir, &routing_path)
+        })
+    )
+}
+
+/* 模拟路由, routing 为空时用当前路由; 可能要解析域名和读 geo 文件, 放到阻塞线程里 */
+#[tauri::command]
+pub async fn simulate_route(
+    app_handle: AppHandle,
+    query: RouteQuery,
+    routing: Option<String>,
+) -> CmdResult<RouteResult> {
+    wrap_err!(run_blocking(move || RouteSimulator::simulate(&app_handle, query, routing)).await)
+}
+
+/* geosite/geoip, 整个文件都要解析, 放到阻塞线程里; file 为空时用 geosite.dat/geoip.dat, 分类列表默认 geosite.dat */
+#[tauri::command]
+pub async fn list_geo_categories(file: Option<String>) -> CmdResult<Vec<GeoCategory>> {
+    let file = file.unwrap_or(GEOSITE_FILE.to_string());
+    wrap_err!(run_blocking(move || GeoData::list_categories(&file)).await)
+}
+
+#[tauri::command]
+pub async fn list_geosite_domains(code: String, file: Option<String>) -> CmdResult<Vec<GeoDomain>> {
+    let file = file.unwrap_or(GEOSITE_FILE.to_string());
+    wrap_err!(run_blocking(move || GeoData::load_site(&file, &code)).await)
+}
+
+#[tauri::command]
+pub async fn list_geoip_cidrs(code: String, file: Option<String>) -> CmdResult<Vec<String>> {
+    let file = file.unwrap_or(GEOIP_FILE.to_string());
+    wrap_err!(
+        run_blocking(move || GeoData::load_ip(&file, &code)
+            .map(|(cidrs, _)| cidrs.iter().map(|cidr| cidr.to_string()).collect()))
+        .await
+    )
+}
+
+#[tauri::command]
+pub async fn find_geosite(domain: String, file: Option<String>) -> CmdResult<Vec<GeoSiteMatch>> {
+    let file = file.unwrap_or(GEOSITE_FILE.to_string());
+    wrap_err!(run_blocking(move || GeoData::find_site(&file, &domain)).await)
+}
+
+#[tauri::command]
+pub async fn find_geoip(ip: String, file: Option<String>) -> CmdResult<Vec<String>> {
+    let file = file.unwrap_or(GEOIP_FILE.to_string());
+    wrap_err!(
+        run_blocking(move || ip
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid ip {}", ip))
+            .and_then(|ip| GeoData::find_ip(&file, ip)))
+        .await
+    )
+}
+
+/* 域名列表导入和订阅 */
+#[tauri::command]
+pub fn import_domain_list(
+    app_handle: AppHandle,
+    name: String,
+    routing: String,
+    outbound_tag: String,
+    content: String,
+    format: Option<ListFormat>,
+) -> CmdResult<ImportResult> {
+    let format = format.unwrap_or_default();
+    wrap_err!(DomainLists::import(
+        &app_handle,
+        &name,
+        &routing,
+        &outbound_tag,
+        &content,
+        format
+    )
+    .and_then(|result| Tray::update_tray(&app_handle).map(|_| result)))
+}
+
+#[tauri::command]
+pub fn list_domain_lists(app_handle: AppHandle) -> CmdResult<Vec<DomainList>> {
+    Ok(DomainLists::list(&app_handle))
+}
+
+#[tauri::command]
+pub fn save_domain_list(app_handle: AppHandle, list: DomainList) -> CmdResult {
+    wrap_err!(DomainLists::save(&app_handle, list))
+}
+
+#[tauri::command]
+pub fn delete_domain_list(app_handle: AppHandle, name: String) -> CmdResult {
+    wrap_err!(DomainLists::delete(&app_handle, &name))
+}
+
+/* 更新后会修改配置里的更新时间, 托盘随配置刷新 */
+#[tauri::command]
+pub async fn refresh_domain_list(app_handle: AppHandle, name: String) -> CmdResult<ImportResult> {
+    wrap_err!(DomainLists::refresh(&app_handle, &name).await)
+}
+
+/* 路由规则块 */
+#[tauri::command]
+pub fn list_routing_blocks() -> CmdResult<Vec<RoutingBlock>> {
+    wrap_err!(RoutingTemplates::list())
+}
+
+#[tauri::command]
+pub fn set_routing_blocks(app_handle: AppHandle, ids: Vec<String>) -> CmdResult {
+    wrap_err!(RoutingTemplates::set_active(&app_handle, ids))
+}
+
+#[tauri::command]
+pub fn set_routing_block_position(app_handle: AppHandle, position: BlockPosition) -> CmdResult {
+    wrap_err!(RoutingTemplates::set_position(&app_handle, position))
+}
+
+#[tauri::command]
+pub fn save_routing_block(
+    app_handle: AppHandle,
+    id: String,
+    name: String,
+    rules: Vec<RuleObject>,
+) -> CmdResult {
+    wrap_err!(RoutingTemplates::save(&app_handle, &id, &name, rules))
+}
+
+#[tauri::command]
+pub fn delete_routing_block(app_handle: AppHandle, id: String) -> CmdResult {
+    wrap_err!(RoutingTemplates::delete(&app_handle, &id))
+}
+
+/* 代理链 */
+#[tauri::command]
+pub fn list_chains(app_handle: AppHandle) -> CmdResult<Vec<OutboundChain>> {
+    Ok(Chains::list(&app_handle))
+}
+
+#[tauri::command]
+pub fn save_chain(app_handle: AppHandle, chain: OutboundChain) -> CmdResult {
+    wrap_err!(Chains::save(&app_handle, chain))
+}
+
+#[tauri::command]
+pub fn delete_chain(app_handle: AppHandle, name: String) -> CmdResult {
+    wrap_err!(Chains::delete(&app_handle, &name))
+}
+
+#[tauri::command]
+pub fn select_chain(app_handle: AppHandle, name: String) -> CmdResult {
+    wrap_err!(Chains::select(&app_handle, &name))
+}
+
+/* 加载的 outbound */
+#[tauri::command]
+pub fn list_loaded_outbounds(app_handle: AppHandle) -> CmdResult<Vec<LoadedOutbound>> {
+    Ok(LoadedOutbounds::list(&app_handle))
+}
+
+#[tauri::command]
+pub fn set_loaded_outbounds(app_handle: AppHandle, loaded: Vec<LoadedOutbound>) -> CmdResult {
+    wrap_err!(LoadedOutbounds::set(&app_handle, loaded))
+}
+
+/* 反向代理 */
+#[tauri::command]
+pub fn list_reverse_bridges(app_handle: AppHandle) -> CmdResult<Vec<ReverseBridge>> {
+    Ok(ReverseProxies::list(&app_handle))
+}
+
+#[tauri::command]
+pub fn save_reverse_bridge(app_handle: AppHandle, bridge: ReverseBridge) -> CmdResult {
+    wrap_err!(ReverseProxies::save(&app_handle, bridge))
+}
+
+#[tauri::command]
+pub fn delete_reverse_bridge(app_handle: AppHandle, name: String) -> CmdResult {
+    wrap_err!(ReverseProxies::delete(&app_handle, &name))
+}
+
+/* 生成两边的配置, 服务器那边的复制过去 */
+#[tauri::command]
+pub fn generate_reverse(app_handle: AppHandle, name: String) -> CmdResult<ReverseConfig> {
+    wrap_err!(ReverseProxies::generate(&app_handle, &name))
+}
+
+/* 服务器配置, reality 的私钥可以填服务器现有的 */
+#[tauri::command]
+pub fn generate_server_config(
+    app_handle: AppHandle,
+    id: String,
+    private_key: Option<String>,
+) -> CmdResult<ServerPair> {
+    wrap_err!(Servers::generate(&app_handle, &id, private_key))
+}
+
+/* 换一对 reality 密钥, 会修改客户端的 outbound */
+#[tauri::command]
+pub fn rotate_server_keys(app_handle: AppHandle, id: String) -> CmdResult<ServerPair> {
+    wrap_err!(Servers::rotate_keys(&app_handle, &id))
+}
+
+#[tauri::command]
+pub fn create_server_node(app_handle: AppHandle, node: NewNode) -> CmdResult<ServerPair> {
+    wrap_err!(Servers::create(&app_handle, node))
+}
+
+#[tauri::command]
+pub fn read_server_config(id: String) -> CmdResult<Value> {
+    wrap_err!(Servers::read(&id))
+}
+
+#[tauri::command]
+pub fn generate_uuid() -> CmdResult<String> {
+    Ok(core::server::new_uuid())
+}
+
+#[tauri::command]
+pub fn generate_x25519() -> CmdResult<X25519Pair> {
+    Ok(core::server::x25519_pair())
+}
+
+#[tauri::command]
+pub fn generate_short_id() -> CmdResult<String> {
+    Ok(core::server::short_id())
+}
+
+/* dns 设置 */
+#[tauri::command]
+pub fn get_dns_settings(app_handle: AppHandle) -> CmdResult<DnsSettings> {
+    Ok(Dns::get(&app_handle))
+}
+
+#[tauri::command]
+pub fn set_dns_settings(app_handle: AppHandle, settings: DnsSettings) -> CmdResult {
+    wrap_err!(Dns::set(&app_handle, settings))
+}
+
+/* 配置文件里的 ${NAME} 变量, 包括内置的 */
+#[tauri::command]
+pub fn list_variables(app_handle: AppHandle) -> CmdResult<BTreeMap<String, String>> {
+    Ok(Variables::list(&app_handle))
+}
+
+/* 只保存自定义变量 */
+#[tauri::command]
+pub fn set_variables(app_handle: AppHandle, variables: BTreeMap<String, String>) -> CmdResult {
+    wrap_err!(Variables::set(&app_handle, variables))
+}
+
 /* 重启xray */
 #[tauri::command]
-pub fn restart_xray() {
-    crate::log_err!(core::xray::Xray::reload_xray())
+pub fn restart_xray(app_handle: AppHandle) {
+    crate::log_err!(core::xray::Xray::reload_xray(&app_handle))
 }
 
 /* 打开目录 */
@@ -53,3 +516,16 @@ pub fn open_log_dir() {
         .map_err(|err| log::error!(target: "app", "[cmd]: {err}"))
         .ok();
 }
+
+/* 打开用户配置目录, 里面的文件暂存时覆盖预设 */
+#[tauri::command]
+pub fn open_user_confdir() {
+    core::path::AppPath::xray_user_config_dir()
+        .context("fail get user confdir")
+        .and_then(|path| {
+            std::fs::create_dir_all(&path)?;
+            open::that(path.clone()).context(format!("fail open path {}", path.display()))
+        })
+        .map_err(|err| log::error!(target: "app", "[cmd]: {err}"))
+        .ok();
+}
@@ -0,0 +1,145 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::AppHandle;
+
+use super::config::IConfig;
+use super::options::OutboundOptions;
+use super::outbound::{main_index, outbound_tags, outbounds_of, retag_outbounds, Outbounds};
+
+/* 前置代理的 tag 前缀, 和 outbound 自己的 tag 区分开 */
+static DIALER_TAG_PREFIX: &str = "dialer-";
+
+/* 代理链: 先经过 dialer 再连 outbound */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct OutboundChain {
+    pub name: String,
+    // 前置代理的 outbound id
+    pub dialer: String,
+    // 最终使用的 outbound id, 它的出站仍然是 proxy
+    pub outbound: String,
+}
+
+pub struct Chains {}
+
+impl Chains {
+    pub fn list(app: &AppHandle) -> Vec<OutboundChain> {
+        IConfig::snapshot(app)
+            .map(|config| config.chains)
+            .unwrap_or_default()
+    }
+
+    /* 当前选择的代理链, 没有选择时用 active_outbound */
+    pub fn active(app: &AppHandle) -> Option<OutboundChain> {
+        let config = IConfig::snapshot(app)?;
+        let name = config.active_chain?;
+        config.chains.into_iter().find(|chain| chain.name == name)
+    }
+
+    /* 保存代理链, 同名覆盖 */
+    pub fn save(app: &AppHandle, chain: OutboundChain) -> Result<()> {
+        if chain.name.is_empty() {
+            anyhow::bail!("chain name is empty");
+        }
+        if chain.dialer == chain.outbound {
+            anyhow::bail!("chain {} dials through itself", chain.name);
+        }
+        for id in [&chain.dialer, &chain.outbound] {
+            if !Outbounds::resolve(id)?.is_file() {
+                anyhow::bail!("outbound {} not found", id);
+            }
+        }
+        IConfig::update(app, |config| {
+            match config
+                .chains
+                .iter_mut()
+                .find(|exist| exist.name == chain.name)
+            {
+                Some(exist) => *exist = chain,
+                None => config.chains.push(chain),
+            }
+        })
+    }
+
+    pub fn delete(app: &AppHandle, name: &str) -> Result<()> {
+        IConfig::update(app, |config| {
+            config.chains.retain(|chain| chain.name != name);
+            if config.active_chain.as_deref() == Some(name) {
+                config.active_chain = None;
+            }
+        })
+    }
+
+    pub fn select(app: &AppHandle, name: &str) -> Result<()> {
+        if !Chains::list(app).iter().any(|chain| chain.name == name) {
+            anyhow::bail!("chain {} not found", name);
+        }
+        IConfig::update(app, |config| config.active_chain = Some(name.to_string()))
+    }
+
+    /* 合并成暂存的 outbound: outbound 的主出站通过 sockopt.dialerProxy 走 dialer
+     * outbound 的分片选项也要用 dialerProxy, 改到 dialer 上, 直接连网络的是 dialer */
+    pub fn render(chain: &OutboundChain) -> Result<Value> {
+        let mut options = Outbounds::options(&chain.outbound)?;
+        let fragment = options.fragment.take();
+        let mut outbound = Outbounds::read(&chain.outbound)?;
+        options.apply(&mut outbound);
+        let mut outbounds = outbounds_of(&outbound, &chain.outbound)?;
+
+        let mut dialer = Outbounds::read_staged(&chain.dialer)?;
+        if let Some(fragment) = fragment {
+            log::info!(target: "app", "[chain]: apply the fragment of {} to dialer {}", chain.outbound, chain.dialer);
+            OutboundOptions {
+                fragment: Some(fragment),
+                ..OutboundOptions::default()
+            }
+            .apply(&mut dialer);
+        }
+        let mut dialers = outbounds_of(&dialer, &chain.dialer)?;
+
+        // dialer 的 tag 全部加前缀, 内部的相互引用一起改
+        let retag = |tag: &str| format!("{}{}", DIALER_TAG_PREFIX, tag);
+        retag_outbounds(&mut dialers, retag);
+
+        let used: Vec<&str> = outbounds
+            .iter()
+            .filter_map(|outbound| outbound.get("tag")?.as_str())
+            .collect();
+        if let Some(tag) = outbound_tags(&dialers)
+            .into_iter()
+            .find(|tag| used.contains(&tag.as_str()))
+        {
+            anyhow::bail!("outbound {} already uses tag {}", chain.outbound, tag);
+        }
+
+        // 两边的主出站: tag 为 proxy 的, 没有时用第一个
+        let dialer_tag = outbound_tags(&dialers)[main_index(&dialers, &retag("proxy"))].clone();
+        let index = main_index(&outbounds, "proxy");
+        let main = &mut outbounds[index];
+        if !matches!(main.get("streamSettings"), Some(Value::Object(_))) {
+            main["streamSettings"] = json!({});
+        }
+        let stream_settings = &mut main["streamSettings"];
+        if !matches!(stream_settings.get("sockopt"), Some(Value::Object(_))) {
+            stream_settings["sockopt"] = json!({});
+        }
+        if let Some(exist) = stream_settings["sockopt"].get("dialerProxy") {
+            log::warn!(target: "app", "[chain]: replace dialerProxy {} of {} with the dialer", exist, chain.outbound);
+        }
+        stream_settings["sockopt"]["dialerProxy"] = Value::from(dialer_tag);
+
+        outbounds.extend(dialers);
+        Ok(json!({ "outbounds": outbounds }))
+    }
+
+    /* outbound 改名时同步代理链 */
+    pub fn rename_outbound(chains: &mut [OutboundChain], id: &str, new_id: &str) {
+        for chain in chains {
+            for reference in [&mut chain.dialer, &mut chain.outbound] {
+                if reference == id {
+                    *reference = new_id.to_string();
+                }
+            }
+        }
+    }
+}
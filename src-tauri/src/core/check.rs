@@ -0,0 +1,170 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use tauri::AppHandle;
+
+use super::format::{read_config, ConfigFormat};
+use super::template::RoutingTemplates;
+use super::variable::Variables;
+
+/* 暂存后的路由文件, 从源文件检查规则 */
+static STAGED_ROUTING: &str = "99.routing.json";
+
+/* 路由规则引用了不存在的 tag */
+#[derive(Debug, Clone, Serialize)]
+pub struct DanglingTag {
+    pub file: String,
+    pub rule_index: usize,
+    pub field: String,
+    pub tag: String,
+}
+
+impl fmt::Display for DanglingTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} rules[{}]: {} \"{}\" is not defined",
+            self.file, self.rule_index, self.field, self.tag
+        )
+    }
+}
+
+/* confdir 里定义的 tag */
+#[derive(Debug, Default)]
+struct DefinedTags {
+    outbounds: HashSet<String>,
+    inbounds: HashSet<String>,
+    balancers: HashSet<String>,
+}
+
+pub struct ConfigCheck {}
+
+impl ConfigCheck {
+    /* 检查路由规则的 outboundTag/balancerTag/inboundTag 是否在暂存的 confdir 里定义 */
+    pub fn check_routing(
+        app: &AppHandle,
+        confdir: &Path,
+        routing_path: &Path,
+    ) -> Result<Vec<DanglingTag>> {
+        let mut tags = DefinedTags::default();
+        for entry in fs::read_dir(confdir)? {
+            let path = entry?.path();
+            if !ConfigFormat::is_config_file(&path)
+                || path.file_name() == Some(STAGED_ROUTING.as_ref())
+            {
+                continue;
+            }
+            tags.collect(&read_config(&path)?);
+        }
+
+        // 和暂存的一样替换变量
+        let routing = Variables::substitute(app, &read_config(routing_path)?)?;
+        // 路由文件里定义的 balancer
+        tags.collect(&routing);
+
+        let file = routing_path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let rules = routing
+            .get("routing")
+            .and_then(|routing| routing.get("rules"))
+            .and_then(|rules| rules.as_array())
+            .cloned()
+            .unwrap_or_default();
+
+        let mut dangling = Vec::new();
+        tags.check_rules(&file, &rules, &mut dangling);
+
+        // 选择的规则块也会暂存
+        for (id, block_rules) in RoutingTemplates::active_rules(app) {
+            let block_rules = Variables::substitute(app, &serde_json::to_value(block_rules)?)?;
+            if let Some(block_rules) = block_rules.as_array() {
+                tags.check_rules(&format!("block {}", id), block_rules, &mut dangling);
+            }
+        }
+
+        Ok(dangling)
+    }
+}
+
+impl DefinedTags {
+    fn check_rules(&self, file: &str, rules: &[Value], dangling: &mut Vec<DanglingTag>) {
+        for (rule_index, rule) in rules.iter().enumerate() {
+            // 禁用的规则不会暂存
+            if rule.get("enabled") == Some(&Value::Bool(false)) {
+                continue;
+            }
+
+            let checks: [(&str, &HashSet<String>); 3] = [
+                ("outboundTag", &self.outbounds),
+                ("balancerTag", &self.balancers),
+                ("inboundTag", &self.inbounds),
+            ];
+            for (field, defined) in checks {
+                for tag in string_list(rule.get(field)) {
+                    if !defined.contains(&tag) {
+                        dangling.push(DanglingTag {
+                            file: file.to_string(),
+                            rule_index,
+                            field: field.to_string(),
+                            tag,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn collect(&mut self, config: &Value) {
+        let tags_of = |value: Option<&Value>| -> Vec<String> {
+            value
+                .and_then(|list| list.as_array())
+                .map(|list| {
+                    list.iter()
+                        .filter_map(|item| item.get("tag")?.as_str().map(|s| s.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        self.outbounds.extend(tags_of(config.get("outbounds")));
+        self.inbounds.extend(tags_of(config.get("inbounds")));
+
+        // dns 模块的 tag 可以作为 inboundTag
+        if let Some(tag) = config
+            .get("dns")
+            .and_then(|dns| dns.get("tag"))
+            .and_then(|tag| tag.as_str())
+        {
+            self.inbounds.insert(tag.to_string());
+        }
+
+        // 反向代理: bridge 的 tag 作为入站, portal 的 tag 作为出站
+        if let Some(reverse) = config.get("reverse") {
+            self.inbounds.extend(tags_of(reverse.get("bridges")));
+            self.outbounds.extend(tags_of(reverse.get("portals")));
+        }
+
+        if let Some(routing) = config.get("routing") {
+            self.balancers.extend(tags_of(routing.get("balancers")));
+        }
+    }
+}
+
+/* tag 字段可以是字符串或字符串数组 */
+fn string_list(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::String(tag)) => vec![tag.clone()],
+        Some(Value::Array(list)) => list
+            .iter()
+            .filter_map(|tag| tag.as_str().map(|s| s.to_string()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
@@ -1,13 +1,28 @@
 use crate::core::path;
-use anyhow::Result;
-use lazy_static::lazy_static;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::fs::{self, File};
-use std::io::BufReader;
-use std::path::PathBuf;
-use std::sync::Mutex;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::AppHandle;
 
+use super::chain::OutboundChain;
+use super::dns::DnsSettings;
+use super::domain_list::DomainList;
+use super::format::{read_config, ConfigFormat};
+use super::loaded::LoadedOutbound;
+use super::outbound::OutboundEntry;
+use super::overlay::ConfdirOverlay;
 use super::path::AppPath;
+use super::profile::Profile;
+use super::reverse::ReverseBridge;
+use super::state::{AppState, Listener};
+use super::store::Store;
+use super::template::BlockPosition;
+use super::variable::Variables;
 
 /* 结构体 */
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -21,143 +36,370 @@ struct InboundsConfigData {
     inbounds: Vec<Inbound>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct UserConfigValue {
-    active_routing: String,
-    active_outbound: String,
-    sys_port_enable: bool,
-    auto_launch_enable: bool, // 新增的字段
+/* 保留的配置备份个数 */
+const CONFIG_BACKUP_KEEP: usize = 5;
+
+/* 每次启动只在第一次写入后备份, 否则备份只是最近几次点击 */
+static CONFIG_BACKED_UP: AtomicBool = AtomicBool::new(false);
+
+/* 当前配置版本, 每新增一个迁移步骤加一 */
+const CONFIG_VERSION: u32 = 1;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/* 迁移步骤, 下标 i 的步骤把 version i 的配置迁移到 version i + 1 */
+static MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [migrate_v0_to_v1];
+
+// v0: 没有 version 字段的旧配置, auto_launch_enable 为后加的字段
+fn migrate_v0_to_v1(config: &mut Map<String, Value>) -> Result<()> {
+    config
+        .entry("auto_launch_enable")
+        .or_insert(Value::Bool(true));
+    Ok(())
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct PortConfig {
+// 新增字段需要有默认值, 缺失字段按默认值补齐
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UserConfigValue {
+    version: u32,
+    pub active_routing: String,
+    pub active_outbound: String,
+    pub sys_port_enable: bool,
+    pub auto_launch_enable: bool, // 新增的字段
+    pub log: LogConfig,
+    // 覆盖预设的入站端口, 为空时使用 05_inbounds.json 里的端口
     pub http_port: Option<u16>,
     pub socks_port: Option<u16>,
+    pub profiles: Vec<Profile>,
+    // 订阅的域名列表
+    pub domain_lists: Vec<DomainList>,
+    // 选择的路由规则块, 按顺序插到当前路由的规则里
+    pub routing_blocks: Vec<String>,
+    // 规则块插入的位置
+    pub routing_block_position: BlockPosition,
+    // 代理链, 选择了代理链时代替 active_outbound
+    pub chains: Vec<OutboundChain>,
+    pub active_chain: Option<String>,
+    // 额外加载的 outbound, 路由规则用各自的 tag 指向
+    pub loaded_outbounds: Vec<LoadedOutbound>,
+    // 反向代理, 本机作为 bridge
+    pub reverse_bridges: Vec<ReverseBridge>,
+    // dns 设置, 关闭时使用预设的 02_dns.json
+    pub dns: DnsSettings,
+    // 自定义变量, 配置文件里用 ${NAME} 引用
+    pub variables: BTreeMap<String, String>,
+}
+
+impl Default for UserConfigValue {
+    fn default() -> Self {
+        UserConfigValue {
+            version: CONFIG_VERSION,
+            active_routing: String::default(),
+            active_outbound: String::default(),
+            sys_port_enable: true,
+            auto_launch_enable: true,
+            log: LogConfig::default(),
+            http_port: None,
+            socks_port: None,
+            profiles: Vec::new(),
+            domain_lists: Vec::new(),
+            routing_blocks: Vec::new(),
+            routing_block_position: BlockPosition::default(),
+            chains: Vec::new(),
+            active_chain: None,
+            loaded_outbounds: Vec::new(),
+            reverse_bridges: Vec::new(),
+            dns: DnsSettings::default(),
+            variables: BTreeMap::new(),
+        }
+    }
+}
+
+/* 日志轮转配置 */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LogConfig {
+    // 单个日志文件最大字节数, 超过后在启动时轮转
+    pub max_file_size: u64,
+    // 轮转出来的日志最多保留天数, 0 表示不按时间清理
+    pub max_age_days: u64,
+    // 轮转出来的日志最多保留个数
+    pub keep_files: usize,
+    // 是否gzip压缩轮转出来的日志
+    pub gzip: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            max_file_size: 10 * 1024 * 1024,
+            max_age_days: 7,
+            keep_files: 5,
+            gzip: true,
+        }
+    }
 }
 
-/* 全局变量 */
-lazy_static! {
-    static ref PORT_CONFIG: Mutex<Option<PortConfig>> = Mutex::new(None);
-    static ref ACTIVE_ROUTING: Mutex<Option<String>> = Mutex::new(None);
-    static ref ACTIVE_OUTBOUND: Mutex<Option<String>> = Mutex::new(None);
-    static ref SYS_PORT_ENABLE: Mutex<Option<bool>> = Mutex::new(Some(false));
-    static ref AUTO_LAUNCH_ENABLE: Mutex<Option<bool>> = Mutex::new(Some(true)); // 新增的全局变量
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PortConfig {
+    pub http_port: Option<u16>,
+    pub socks_port: Option<u16>,
 }
 
 pub struct IConfig {}
 
 impl IConfig {
-    pub fn active_routing() -> Option<String> {
-        ACTIVE_ROUTING.lock().ok().and_then(|v| v.clone())
+    /* 当前配置的一致快照 */
+    pub fn snapshot(app: &AppHandle) -> Option<UserConfigValue> {
+        AppState::get(app).ok().map(|state| state.snapshot())
     }
 
-    pub fn active_outbound() -> Option<String> {
-        ACTIVE_OUTBOUND.lock().ok().and_then(|v| v.clone())
+    pub fn active_routing(app: &AppHandle) -> Option<String> {
+        IConfig::snapshot(app).map(|config| config.active_routing)
     }
 
-    pub fn sys_port_enable() -> Option<bool> {
-        SYS_PORT_ENABLE.lock().ok().and_then(|v| *v)
+    pub fn active_outbound(app: &AppHandle) -> Option<String> {
+        IConfig::snapshot(app).map(|config| config.active_outbound)
     }
 
-    pub fn auto_launch_enable() -> Option<bool> {
-        AUTO_LAUNCH_ENABLE.lock().ok().and_then(|v| *v)
+    pub fn sys_port_enable(app: &AppHandle) -> Option<bool> {
+        IConfig::snapshot(app).map(|config| config.sys_port_enable)
     }
 
-    pub fn port_config() -> Option<PortConfig> {
-        PORT_CONFIG.lock().ok().and_then(|v| v.clone())
+    pub fn auto_launch_enable(app: &AppHandle) -> Option<bool> {
+        IConfig::snapshot(app).map(|config| config.auto_launch_enable)
     }
 
-    pub fn init_config() -> Result<()> {
-        let user_config_json = IConfig::get_init_user_config();
+    pub fn log_config(app: &AppHandle) -> Option<LogConfig> {
+        IConfig::snapshot(app).map(|config| config.log)
+    }
 
-        let active_routing = user_config_json.active_routing;
-        ACTIVE_ROUTING
-            .lock()
-            .map(|mut v| *v = Some(active_routing))
-            .ok();
+    /* 实际使用的端口, 用户配置优先 */
+    pub fn port_config(app: &AppHandle) -> Option<PortConfig> {
+        let state = AppState::get(app).ok()?;
+        let preset = state.port_config();
+        let config = state.snapshot();
+        Some(PortConfig {
+            http_port: config.http_port.or(preset.http_port),
+            socks_port: config.socks_port.or(preset.socks_port),
+        })
+    }
 
-        let active_outbound = user_config_json.active_outbound;
-        ACTIVE_OUTBOUND
-            .lock()
-            .map(|mut v| *v = Some(active_outbound))
-            .ok();
+    pub fn init_config(app: &AppHandle) -> Result<()> {
+        let user_config = IConfig::get_init_user_config();
+        let port_config = IConfig::get_init_port_config(app);
 
-        let sys_port_enable = user_config_json.sys_port_enable;
-        SYS_PORT_ENABLE
-            .lock()
-            .map(|mut v| *v = Some(sys_port_enable))
-            .ok();
+        // 持久化迁移后的配置, 新版本写入的配置备份失败时不覆盖
+        match IConfig::backup_newer_config() {
+            Ok(()) => IConfig::persist(&user_config)?,
+            Err(err) => log::error!(target: "app", "skip persisting config: {err}"),
+        }
 
-        let auto_launch_enable = user_config_json.auto_launch_enable;
-        AUTO_LAUNCH_ENABLE
-            .lock()
-            .map(|mut v| *v = Some(auto_launch_enable))
-            .ok();
+        AppState::init(app, user_config, port_config)
+    }
 
-        let port_config = IConfig::get_init_port_config();
-        PORT_CONFIG.lock().map(|mut v| *v = Some(port_config)).ok();
+    /* 修改配置, 闭包里的多个字段修改一起生效 */
+    pub fn update<F>(app: &AppHandle, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut UserConfigValue),
+    {
+        AppState::get(app)?.update(app, f)
+    }
 
+    /* 订阅配置变化 */
+    pub fn subscribe<F>(app: &AppHandle, listener: F) -> Result<()>
+    where
+        F: Fn(&AppHandle, &UserConfigValue, &UserConfigValue) + Send + Sync + 'static,
+    {
+        let listener: Listener = std::sync::Arc::new(listener);
+        AppState::get(app)?.subscribe(listener);
         Ok(())
     }
 
-    pub fn set_active_routing(new_data: String) -> Result<()> {
-        ACTIVE_ROUTING.lock().map(|mut v| *v = Some(new_data)).ok();
-        IConfig::write_config()?;
-        Ok(())
+    pub fn set_active_routing(app: &AppHandle, new_data: String) -> Result<()> {
+        IConfig::update(app, |config| config.active_routing = new_data)
     }
 
-    pub fn set_active_outbound(new_data: String) -> Result<()> {
-        ACTIVE_OUTBOUND.lock().map(|mut v| *v = Some(new_data)).ok();
-        IConfig::write_config()?;
-        Ok(())
+    /* 选择单个 outbound 时退出代理链 */
+    pub fn set_active_outbound(app: &AppHandle, new_data: String) -> Result<()> {
+        IConfig::update(app, |config| {
+            config.active_outbound = new_data;
+            config.active_chain = None;
+        })
     }
-    pub fn set_sys_port_enable(new_data: bool) -> Result<()> {
-        SYS_PORT_ENABLE.lock().map(|mut v| *v = Some(new_data)).ok();
-        IConfig::write_config()?;
+
+    pub fn set_sys_port_enable(app: &AppHandle, new_data: bool) -> Result<()> {
+        IConfig::update(app, |config| config.sys_port_enable = new_data)
+    }
+
+    pub fn write_config(app: &AppHandle) -> Result<()> {
+        let config = IConfig::snapshot(app).ok_or(anyhow::anyhow!("failed to get config"))?;
+        IConfig::persist(&config)
+    }
+
+    pub fn persist(config: &UserConfigValue) -> Result<()> {
+        let json_str = serde_json::to_string(config)?;
+
+        let config_path = AppPath::config_json()?;
+        Store::write_atomic(&config_path, json_str.as_bytes())?;
+        // 配置已经写入, 备份失败不影响这次修改
+        if !CONFIG_BACKED_UP.swap(true, Ordering::SeqCst) {
+            crate::log_err!(IConfig::backup_config(&config_path));
+        }
+
         Ok(())
     }
 
-    pub fn write_config() -> Result<()> {
-        let new_config = UserConfigValue {
-            active_routing: IConfig::active_routing().unwrap_or_default(),
-            active_outbound: IConfig::active_outbound().unwrap_or_default(),
-            sys_port_enable: IConfig::sys_port_enable().unwrap_or_default(),
-            auto_launch_enable: IConfig::auto_launch_enable().unwrap_or_default(),
+    fn backup_config(config_path: &Path) -> Result<()> {
+        Store::backup(
+            config_path,
+            &AppPath::config_backup_dir()?,
+            CONFIG_BACKUP_KEEP,
+        )
+    }
+
+    pub fn get_init_user_config() -> UserConfigValue {
+        let config_path = match AppPath::config_json() {
+            Ok(config_path) if config_path.exists() => config_path,
+            _ => return UserConfigValue::default(),
         };
-        let json_str = serde_json::to_string(&new_config)?;
 
+        IConfig::read_user_config().unwrap_or_else(|err| {
+            log::error!(target: "app", "failed to parse {}: {err}", config_path.display());
+            // 解析失败的配置备份起来, 不直接丢弃
+            crate::log_err!(IConfig::backup_broken_config(&config_path));
+            IConfig::recover_user_config().unwrap_or_default()
+        })
+    }
+
+    /* 从最新的可用备份恢复 */
+    fn recover_user_config() -> Option<UserConfigValue> {
+        let config_path = AppPath::config_json().ok()?;
+        let backups = Store::backups(&config_path, &AppPath::config_backup_dir().ok()?).ok()?;
+        backups.iter().find_map(|backup_path| {
+            match IConfig::parse_user_config(backup_path) {
+                Ok(config) => {
+                    log::warn!(target: "app", "recover config from {}", backup_path.display());
+                    Some(config)
+                }
+                Err(err) => {
+                    log::warn!(target: "app", "skip broken backup {}: {err}", backup_path.display());
+                    None
+                }
+            }
+        })
+    }
+
+    /* 读取配置并按版本迁移 */
+    pub fn read_user_config() -> Result<UserConfigValue> {
+        IConfig::parse_user_config(&AppPath::config_json()?)
+    }
+
+    fn parse_user_config(config_path: &Path) -> Result<UserConfigValue> {
+        let json_str = fs::read_to_string(config_path)?;
+        let mut config: Map<String, Value> = serde_json::from_str(json_str.as_str())?;
+
+        let version = config
+            .get("version")
+            .and_then(|version| version.as_u64())
+            .unwrap_or(0) as usize;
+        if version > MIGRATIONS.len() {
+            log::warn!(target: "app", "config version {version} is newer than {CONFIG_VERSION}");
+        }
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            log::info!(target: "app", "migrate config from version {from} to {}", from + 1);
+            migration(&mut config)?;
+            config.insert("version".to_string(), Value::from(from + 1));
+        }
+
+        Ok(serde_json::from_value(Value::Object(config))?)
+    }
+
+    /* 新版本写入的配置复制一份, 持久化时不认识的字段会丢掉, 降级后可以手动恢复 */
+    fn backup_newer_config() -> Result<()> {
         let config_path = AppPath::config_json()?;
-        fs::write(config_path, json_str.as_bytes())?;
+        if !config_path.exists() {
+            return Ok(());
+        }
+        let json_str = fs::read_to_string(&config_path)?;
+        let config: Map<String, Value> = serde_json::from_str(json_str.as_str())?;
+        let version = config
+            .get("version")
+            .and_then(|version| version.as_u64())
+            .unwrap_or(0);
+        if version <= u64::from(CONFIG_VERSION) {
+            return Ok(());
+        }
 
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let backup_path = config_path.with_extension(format!("json.v{version}-{timestamp}"));
+        log::warn!(target: "app", "backup config of version {version} to {}", backup_path.display());
+        fs::copy(&config_path, backup_path)?;
         Ok(())
     }
 
-    pub fn get_init_user_config() -> UserConfigValue {
-        AppPath::config_json()
-            .ok()
-            .and_then(|file_path| File::open(file_path).ok())
-            .map(BufReader::new)
-            .and_then(|reader| {
-                let result: Result<UserConfigValue, _> = serde_json::from_reader(reader);
-                result.ok()
-            })
-            .unwrap_or(UserConfigValue {
-                active_routing: String::default(),
-                active_outbound: String::default(),
-                sys_port_enable: true,
-                auto_launch_enable: true,
-            })
+    fn backup_broken_config(config_path: &Path) -> Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let backup_path = config_path.with_extension(format!("json.broken-{timestamp}"));
+        log::warn!(target: "app", "backup broken config to {}", backup_path.display());
+        fs::rename(config_path, backup_path)?;
+        Ok(())
     }
 
-    pub fn get_init_port_config() -> PortConfig {
-        let parsed_data = path::AppPath::xray_preset_config_dir()
+    /* 预设的入站端口, 用户 confdir 里的 05_inbounds 覆盖预设 */
+    pub fn get_init_port_config(app: &AppHandle) -> PortConfig {
+        let inbounds = path::AppPath::xray_preset_config_dir()
             .map(|path| path.join("05_inbounds.json"))
             .ok()
-            .and_then(|file_path| fs::read_to_string(file_path).ok())
-            .and_then(|json_str| {
-                let result: Result<InboundsConfigData, _> = serde_json::from_str(json_str.as_str());
-                result.ok()
+            .and_then(|file_path| read_config(&file_path).ok())
+            .and_then(|value| ConfdirOverlay::overlaid("05_inbounds", value).ok())
+            .and_then(|value| {
+                IConfig::substitute_inbound_ports(app, value)
+                    .map_err(|err| log::warn!(target: "app", "[port]: {err}"))
+                    .ok()
             });
+        IConfig::port_config_of(inbounds)
+    }
+
+    /* 按暂存的入站更新预设端口, 用户 confdir 可能在启动后改过, 返回端口是否变化 */
+    pub fn refresh_port_config(app: &AppHandle, inbounds_path: &Path) -> Result<bool> {
+        let inbounds = read_config(inbounds_path)
+            .ok()
+            .map(|value| {
+                IConfig::substitute_inbound_ports(app, value)
+                    .with_context(|| format!("failed to read ports of {}", inbounds_path.display()))
+            })
+            .transpose()?;
+        let port_config = IConfig::port_config_of(inbounds);
+        let state = AppState::get(app)?;
+        let changed = state.port_config() != port_config;
+        if changed {
+            state.set_port_config(port_config);
+        }
+        Ok(changed)
+    }
+
+    /* 用户 confdir 里的端口可以是变量, 先替换再按数字读 */
+    fn substitute_inbound_ports(app: &AppHandle, mut value: Value) -> Result<Value> {
+        if let Some(inbounds) = value
+            .get_mut("inbounds")
+            .and_then(|inbounds| inbounds.as_array_mut())
+        {
+            for inbound in inbounds {
+                if let Some(port) = inbound.get_mut("port") {
+                    *port = Variables::substitute_port(app, port)?;
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    fn port_config_of(inbounds: Option<Value>) -> PortConfig {
+        let parsed_data = inbounds.and_then(|value| {
+            let result: Result<InboundsConfigData, _> = serde_json::from_value(value);
+            result.ok()
+        });
 
         let http_port: Option<u16> = parsed_data.clone().and_then(|parsed_data| {
             parsed_data
@@ -189,7 +431,7 @@ impl IConfig {
                 let file_paths: Vec<PathBuf> = entries
                     .filter_map(|entry| entry.ok())
                     .filter(|entry| {
-                        entry.path().is_file() && entry.path().extension() == Some("json".as_ref())
+                        entry.path().is_file() && ConfigFormat::is_config_file(&entry.path())
                     })
                     .map(|entry| entry.path())
                     .collect();
@@ -198,17 +440,21 @@ impl IConfig {
         path_list
     }
 
-    pub fn get_outbound_list() -> Option<Vec<PathBuf>> {
-        let path_list: Option<Vec<PathBuf>> = path::AppPath::xray_outbound_dir()
+    /* outbound 列表, 包含子目录, 按目录/分组/名字排序 */
+    pub fn get_outbound_list() -> Option<Vec<OutboundEntry>> {
+        let outbound_list: Option<Vec<OutboundEntry>> = path::AppPath::xray_outbound_dir()
             .ok()
-            .and_then(|path| fs::read_dir(path).ok())
-            .map(|entries| {
-                let file_paths: Vec<PathBuf> = entries
-                    .filter_map(|entry| entry.ok())
-                    .map(|entry| entry.path())
-                    .collect();
-                file_paths
+            .and_then(|path| OutboundEntry::scan(&path).ok())
+            .map(|mut outbounds| {
+                outbounds.sort_by(|a, b| {
+                    (&a.folder, a.group(), &a.display_name).cmp(&(
+                        &b.folder,
+                        b.group(),
+                        &b.display_name,
+                    ))
+                });
+                outbounds
             });
-        path_list
+        outbound_list
     }
 }
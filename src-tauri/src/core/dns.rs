@@ -0,0 +1,273 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use tauri::AppHandle;
+
+use super::config::IConfig;
+use super::geodata::Cidr;
+
+/* 暂存的 dns 配置, 出站放在最后 */
+static STAGED_DNS: &str = "95.dns.tail.json";
+
+/* 内置 dns 发出的查询的 inboundTag */
+static DNS_QUERY_TAG: &str = "dns-query";
+
+/* 劫持到内置 dns 的出站 */
+static DNS_OUTBOUND_TAG: &str = "dns-out";
+
+/* xray 支持的 dns 服务器地址前缀, 不支持 DoT */
+static SERVER_SCHEMES: &[&str] = &[
+    "https://",
+    "https+local://",
+    "quic+local://",
+    "tcp://",
+    "tcp+local://",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum QueryStrategy {
+    #[default]
+    UseIP,
+    UseIPv4,
+    UseIPv6,
+}
+
+/* 一个 dns 服务器, domains 为空时用于所有域名 */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DnsServer {
+    // ip, localhost 或 https://, https+local://, quic+local://, tcp:// 地址
+    pub address: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    // 只用这个服务器查询的域名, 和路由规则的写法一样
+    #[serde(default)]
+    pub domains: Vec<String>,
+    // 只接受在这些范围内的结果, ip 或 geoip:
+    #[serde(default)]
+    pub expect_ips: Vec<String>,
+    // 查询其它域名时不使用
+    #[serde(default)]
+    pub skip_fallback: bool,
+}
+
+/* dns 设置, 关闭时使用预设的 02_dns.json */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DnsSettings {
+    pub enabled: bool,
+    pub servers: Vec<DnsServer>,
+    // 域名 -> ip 或另一个域名
+    pub hosts: BTreeMap<String, Vec<String>>,
+    pub query_strategy: QueryStrategy,
+    pub fake_dns: bool,
+    pub fake_dns_pool: String,
+    // 内置 dns 的查询走 proxy, 关闭时直连
+    pub through_proxy: bool,
+}
+
+impl Default for DnsSettings {
+    fn default() -> Self {
+        DnsSettings {
+            enabled: false,
+            servers: Vec::new(),
+            hosts: BTreeMap::new(),
+            query_strategy: QueryStrategy::default(),
+            fake_dns: false,
+            fake_dns_pool: "198.18.0.0/15".to_string(),
+            through_proxy: false,
+        }
+    }
+}
+
+pub struct Dns {}
+
+impl Dns {
+    pub fn get(app: &AppHandle) -> DnsSettings {
+        IConfig::snapshot(app)
+            .map(|config| config.dns)
+            .unwrap_or_default()
+    }
+
+    pub fn set(app: &AppHandle, settings: DnsSettings) -> Result<()> {
+        Dns::validate(&settings)?;
+        IConfig::update(app, |config| config.dns = settings)
+    }
+
+    fn validate(settings: &DnsSettings) -> Result<()> {
+        if settings.enabled && settings.servers.is_empty() && !settings.fake_dns {
+            anyhow::bail!("no dns server");
+        }
+        for server in &settings.servers {
+            let address = server.address.as_str();
+            if address.starts_with("tls://") {
+                anyhow::bail!(
+                    "xray does not support DoT server {}, use https:// or quic+local://",
+                    address
+                );
+            }
+            let valid = address == "localhost"
+                || address.parse::<IpAddr>().is_ok()
+                || SERVER_SCHEMES
+                    .iter()
+                    .any(|scheme| address.len() > scheme.len() && address.starts_with(scheme));
+            if !valid {
+                anyhow::bail!("invalid dns server {}", address);
+            }
+            if server.expect_ips.iter().any(|ip| ip.is_empty()) {
+                anyhow::bail!("dns server {} has an empty expect ip", address);
+            }
+        }
+        for (domain, addresses) in &settings.hosts {
+            if domain.is_empty() || addresses.is_empty() {
+                anyhow::bail!("invalid hosts entry {}", domain);
+            }
+        }
+        if settings.fake_dns && Cidr::parse(&settings.fake_dns_pool).is_none() {
+            anyhow::bail!("invalid fake dns pool {}", settings.fake_dns_pool);
+        }
+        Ok(())
+    }
+
+    /* 暂存的 dns 配置: dns, fakedns 和劫持 dns 请求的出站 */
+    pub fn render(settings: &DnsSettings) -> Value {
+        let mut servers: Vec<Value> = Vec::new();
+        if settings.fake_dns {
+            servers.push(Value::from("fakedns"));
+        }
+        for server in &settings.servers {
+            // 只有地址时用简写
+            if server.port.is_none()
+                && server.domains.is_empty()
+                && server.expect_ips.is_empty()
+                && !server.skip_fallback
+            {
+                servers.push(Value::from(server.address.clone()));
+                continue;
+            }
+            let mut object = Map::new();
+            object.insert("address".to_string(), Value::from(server.address.clone()));
+            if let Some(port) = server.port {
+                object.insert("port".to_string(), Value::from(port));
+            }
+            if !server.domains.is_empty() {
+                object.insert("domains".to_string(), json!(server.domains));
+            }
+            if !server.expect_ips.is_empty() {
+                object.insert("expectIPs".to_string(), json!(server.expect_ips));
+            }
+            if server.skip_fallback {
+                object.insert("skipFallback".to_string(), Value::Bool(true));
+            }
+            servers.push(Value::Object(object));
+        }
+
+        let hosts: Map<String, Value> = settings
+            .hosts
+            .iter()
+            .map(|(domain, addresses)| {
+                let value = match addresses.as_slice() {
+                    [address] => Value::from(address.clone()),
+                    _ => json!(addresses),
+                };
+                (domain.clone(), value)
+            })
+            .collect();
+
+        let mut config = json!({
+            "dns": {
+                "servers": servers,
+                "hosts": hosts,
+                "queryStrategy": settings.query_strategy,
+                "tag": DNS_QUERY_TAG
+            },
+            "outbounds": [{ "protocol": "dns", "tag": DNS_OUTBOUND_TAG }]
+        });
+        if settings.fake_dns {
+            config["fakedns"] = json!([{ "ipPool": settings.fake_dns_pool, "poolSize": 65535 }]);
+        }
+        config
+    }
+
+    /* 暂存 dns, 劫持 dns 请求和查询走代理的规则插到暂存路由的最前面 */
+    pub fn stage(app: &AppHandle, confdir: &Path, routing: &mut Value) -> Result<()> {
+        let settings = Dns::get(app);
+        if !settings.enabled {
+            return Ok(());
+        }
+
+        // 内置 dns 自己的查询在前面, 不然也会被劫持回内置 dns
+        let rules = vec![
+            json!({
+                "type": "field",
+                "inboundTag": [DNS_QUERY_TAG],
+                "outboundTag": if settings.through_proxy { "proxy" } else { "direct" }
+            }),
+            json!({
+                "type": "field",
+                "network": "udp",
+                "port": 53,
+                "outboundTag": DNS_OUTBOUND_TAG
+            }),
+        ];
+        let Some(routing) = routing
+            .get_mut("routing")
+            .and_then(|routing| routing.as_object_mut())
+        else {
+            anyhow::bail!("invalid staged routing");
+        };
+        if let Some(staged_rules) = routing
+            .entry("rules")
+            .or_insert(Value::Array(Vec::new()))
+            .as_array_mut()
+        {
+            staged_rules.splice(0..0, rules);
+        }
+
+        // fakedns 需要入站嗅探出域名
+        if settings.fake_dns {
+            Dns::patch_sniffing(&confdir.join("05_inbounds.json"))?;
+        }
+
+        fs::write(
+            confdir.join(STAGED_DNS),
+            serde_json::to_string_pretty(&Dns::render(&settings))?,
+        )?;
+        Ok(())
+    }
+
+    fn patch_sniffing(inbounds_path: &Path) -> Result<()> {
+        let json_str = fs::read_to_string(inbounds_path)?;
+        let mut inbounds_config: Value = serde_json::from_str(json_str.as_str())?;
+        if let Some(inbounds) = inbounds_config
+            .get_mut("inbounds")
+            .and_then(|inbounds| inbounds.as_array_mut())
+        {
+            for inbound in inbounds {
+                let Some(sniffing) = inbound
+                    .get_mut("sniffing")
+                    .and_then(|sniffing| sniffing.as_object_mut())
+                else {
+                    continue;
+                };
+                if let Some(dest_override) = sniffing
+                    .entry("destOverride")
+                    .or_insert(Value::Array(Vec::new()))
+                    .as_array_mut()
+                {
+                    if !dest_override.iter().any(|dest| dest == "fakedns") {
+                        dest_override.push(Value::from("fakedns"));
+                    }
+                }
+            }
+        }
+        fs::write(
+            inbounds_path,
+            serde_json::to_string_pretty(&inbounds_config)?,
+        )?;
+        Ok(())
+    }
+}
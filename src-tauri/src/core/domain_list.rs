@@ -0,0 +1,399 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::AppHandle;
+
+use super::config::IConfig;
+use super::routing::{Routings, QUICK_RULE_PREFIX};
+use super::schema::{RoutingConfig, RuleObject};
+
+/* 导入的域名列表生成的规则的 ruleTag 前缀 */
+static LIST_RULE_PREFIX: &str = "list-";
+
+/* 检查定时更新的间隔 */
+const REFRESH_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/* 启动后等 xray 起来再检查, 通过代理下载时才有入站可用 */
+const FIRST_REFRESH_DELAY: Duration = Duration::from_secs(60);
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListFormat {
+    // gfwlist 或者普通列表
+    #[default]
+    Auto,
+    // 一行一个域名或 domain:/full:/regexp:/keyword: 规则
+    Plain,
+    // base64 编码的 ABP 语法
+    Gfwlist,
+}
+
+/* 从 url 订阅的域名列表, 定时更新到路由文件 */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DomainList {
+    pub name: String,
+    pub url: String,
+    // 写入的路由文件
+    pub routing: String,
+    pub outbound_tag: String,
+    #[serde(default)]
+    pub format: ListFormat,
+    // 更新间隔小时数, 0 表示只手动更新
+    #[serde(default)]
+    pub interval_hours: u64,
+    // 通过本地 http 入站下载
+    #[serde(default)]
+    pub via_proxy: bool,
+    // 上次更新的时间戳(秒)
+    #[serde(default)]
+    pub updated_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportResult {
+    pub routing: String,
+    pub rule_tag: String,
+    pub count: usize,
+    // 转换不了的行数, 如 ip 和带通配符的规则
+    pub skipped: usize,
+    // gfwlist 里 @@ 开头的例外规则, 不会生成规则, 这些域名仍然走 outbound_tag
+    pub exceptions: usize,
+}
+
+pub struct DomainLists {}
+
+impl DomainLists {
+    /* 把列表转成一条规则写进路由文件, 同名的规则整条替换 */
+    pub fn import(
+        app: &AppHandle,
+        name: &str,
+        routing: &str,
+        outbound_tag: &str,
+        content: &str,
+        format: ListFormat,
+    ) -> Result<ImportResult> {
+        if name.is_empty() {
+            anyhow::bail!("list name is empty");
+        }
+        let (matchers, skipped, exceptions) = parse_list(content, format)?;
+        if exceptions > 0 {
+            log::warn!(
+                target: "app",
+                "[domain list]: {} exception rules in list {} are not supported and skipped",
+                exceptions,
+                name
+            );
+        }
+        if matchers.is_empty() {
+            anyhow::bail!("no domain found in list {}", name);
+        }
+
+        let rule_tag = format!("{}{}", LIST_RULE_PREFIX, name);
+        let count = matchers.len();
+        let rule = RuleObject {
+            domain: Some(matchers),
+            outbound_tag: Some(outbound_tag.to_string()),
+            rule_tag: Some(rule_tag.clone()),
+            ..RuleObject::default()
+        };
+
+        let mut config = match Routings::resolve(routing)?.exists() {
+            true => Routings::read(app, routing)?,
+            false => RoutingConfig::validate(&json!({
+                "routing": {
+                    "domainStrategy": "IPIfNonMatch",
+                    "rules": []
+                }
+            }))?,
+        };
+        let rules = &mut config.routing.rules;
+        match rules
+            .iter_mut()
+            .find(|exist| exist.rule_tag.as_deref() == Some(rule_tag.as_str()))
+        {
+            Some(exist) => {
+                // 保留启用状态
+                let enabled = exist.enabled;
+                *exist = RuleObject { enabled, ..rule };
+            }
+            // 放在托盘快捷规则后面
+            None => {
+                let index = rules
+                    .iter()
+                    .take_while(|rule| {
+                        rule.rule_tag
+                            .as_deref()
+                            .is_some_and(|tag| tag.starts_with(QUICK_RULE_PREFIX))
+                    })
+                    .count();
+                rules.insert(index, rule);
+            }
+        }
+        Routings::write(app, routing, &config)?;
+
+        Ok(ImportResult {
+            routing: routing.to_string(),
+            rule_tag,
+            count,
+            skipped,
+            exceptions,
+        })
+    }
+
+    /* 订阅 */
+    pub fn list(app: &AppHandle) -> Vec<DomainList> {
+        IConfig::snapshot(app)
+            .map(|config| config.domain_lists)
+            .unwrap_or_default()
+    }
+
+    /* 保存订阅, 同名覆盖 */
+    pub fn save(app: &AppHandle, list: DomainList) -> Result<()> {
+        if list.name.is_empty() {
+            anyhow::bail!("list name is empty");
+        }
+        if list.url.is_empty() {
+            anyhow::bail!("list url is empty");
+        }
+        Routings::resolve(&list.routing)?;
+        IConfig::update(app, |config| {
+            match config
+                .domain_lists
+                .iter_mut()
+                .find(|exist| exist.name == list.name)
+            {
+                Some(exist) => *exist = list,
+                None => config.domain_lists.push(list),
+            }
+        })
+    }
+
+    /* 只删除订阅, 路由文件里的规则保留 */
+    pub fn delete(app: &AppHandle, name: &str) -> Result<()> {
+        IConfig::update(app, |config| {
+            config.domain_lists.retain(|list| list.name != name)
+        })
+    }
+
+    /* 下载并更新路由文件 */
+    pub async fn refresh(app: &AppHandle, name: &str) -> Result<ImportResult> {
+        let list = DomainLists::list(app)
+            .into_iter()
+            .find(|list| list.name == name)
+            .ok_or(anyhow::anyhow!("domain list {} not found", name))?;
+
+        let content = DomainLists::fetch(app, &list).await?;
+        // 写入路由文件会重启 xray, 放到阻塞线程里, 不占用异步运行时的线程
+        let app = app.clone();
+        tauri::async_runtime::spawn_blocking(move || -> Result<ImportResult> {
+            let result = DomainLists::import(
+                &app,
+                &list.name,
+                &list.routing,
+                &list.outbound_tag,
+                &content,
+                list.format,
+            )?;
+
+            let now = now_secs();
+            IConfig::update(&app, |config| {
+                if let Some(exist) = config
+                    .domain_lists
+                    .iter_mut()
+                    .find(|exist| exist.name == list.name)
+                {
+                    exist.updated_at = Some(now);
+                }
+            })?;
+            Ok(result)
+        })
+        .await?
+    }
+
+    /* 定时检查到期的订阅 */
+    pub fn start_scheduler(app: &AppHandle) {
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            tokio::time::sleep(FIRST_REFRESH_DELAY).await;
+            loop {
+                let now = now_secs();
+                for list in DomainLists::list(&app) {
+                    let due = list.interval_hours > 0
+                        && !list.updated_at.is_some_and(|updated_at| {
+                            updated_at + list.interval_hours * 3600 > now
+                        });
+                    if !due {
+                        continue;
+                    }
+                    match DomainLists::refresh(&app, &list.name).await {
+                        Ok(result) => log::info!(
+                            target: "app",
+                            "[domain list]: {} updated, {} domains",
+                            list.name,
+                            result.count
+                        ),
+                        Err(err) => {
+                            log::error!(target: "app", "[domain list]: {} {err}", list.name)
+                        }
+                    }
+                }
+                tokio::time::sleep(REFRESH_CHECK_INTERVAL).await;
+            }
+        });
+    }
+
+    async fn fetch(app: &AppHandle, list: &DomainList) -> Result<String> {
+        let mut builder = reqwest::Client::builder().timeout(FETCH_TIMEOUT);
+        if list.via_proxy {
+            let http_port = IConfig::port_config(app)
+                .and_then(|port_config| port_config.http_port)
+                .ok_or(anyhow::anyhow!("failed to get http port"))?;
+            builder = builder.proxy(reqwest::Proxy::all(format!(
+                "http://127.0.0.1:{}",
+                http_port
+            ))?);
+        }
+        let response = builder
+            .build()?
+            .get(&list.url)
+            .send()
+            .await
+            .with_context(|| format!("failed to fetch {}", list.url))?
+            .error_for_status()?;
+        Ok(response.text().await?)
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/* 转成 xray 的 domain 规则, 返回 (规则, 跳过的行数, 跳过的例外规则数) */
+pub fn parse_list(content: &str, format: ListFormat) -> Result<(Vec<String>, usize, usize)> {
+    let (text, is_gfwlist) = match format {
+        ListFormat::Plain => (content.to_string(), false),
+        ListFormat::Gfwlist => (
+            decode_gfwlist(content).ok_or(anyhow::anyhow!("invalid gfwlist"))?,
+            true,
+        ),
+        ListFormat::Auto => match decode_gfwlist(content) {
+            Some(text) => (text, true),
+            None => (content.to_string(), false),
+        },
+    };
+
+    let mut seen = HashSet::new();
+    let mut matchers = Vec::new();
+    let mut skipped = 0;
+    let mut exceptions = 0;
+    for line in text.lines().map(|line| line.trim()) {
+        if line.is_empty() || line.starts_with(['!', '#', '[']) || line.starts_with("//") {
+            continue;
+        }
+        if is_gfwlist && line.starts_with("@@") {
+            exceptions += 1;
+            continue;
+        }
+        let matcher = if is_gfwlist {
+            gfwlist_matcher(line)
+        } else {
+            plain_matcher(line)
+        };
+        match matcher {
+            Some(matcher) => {
+                if seen.insert(matcher.clone()) {
+                    matchers.push(matcher);
+                }
+            }
+            None => skipped += 1,
+        }
+    }
+    Ok((matchers, skipped, exceptions))
+}
+
+/* gfwlist 是 base64 编码的, 也接受解码后的文本 */
+fn decode_gfwlist(content: &str) -> Option<String> {
+    if content.trim_start().starts_with("[AutoProxy") {
+        return Some(content.to_string());
+    }
+    let encoded: String = content.split_whitespace().collect();
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .ok()?;
+    String::from_utf8(decoded).ok()
+}
+
+/* 一行一个域名, 已经带前缀的规则原样保留 */
+fn plain_matcher(line: &str) -> Option<String> {
+    if let Some((prefix, value)) = line.split_once(':') {
+        return match prefix {
+            "domain" | "full" | "keyword" => Some(format!("{}:{}", prefix, value.to_lowercase())),
+            "regexp" => Regex::new(value).is_ok().then(|| line.to_string()),
+            _ => None,
+        };
+    }
+    let host = line
+        .trim_start_matches("*.")
+        .trim_start_matches('.')
+        .to_lowercase();
+    valid_host(&host).then(|| format!("domain:{}", host))
+}
+
+/* ABP 语法: ||domain, |url, /regex/, 普通的按域名处理, @@ 例外规则在 parse_list 里单独统计 */
+fn gfwlist_matcher(line: &str) -> Option<String> {
+    if line.len() > 2 && line.starts_with('/') && line.ends_with('/') {
+        return gfwlist_regexp(&line[1..line.len() - 1]);
+    }
+
+    let url = line.trim_start_matches("||").trim_start_matches('|');
+    let url = url
+        .strip_prefix("http://")
+        .or_else(|| url.strip_prefix("https://"))
+        .unwrap_or(url);
+    let host = url
+        .split(['/', '^', ':', '?'])
+        .next()?
+        .trim_start_matches('.')
+        .to_lowercase();
+    valid_host(&host).then(|| format!("domain:{}", host))
+}
+
+/* gfwlist 的正则匹配 url, 去掉协议和路径后只匹配域名的才转换 */
+fn gfwlist_regexp(pattern: &str) -> Option<String> {
+    let mut pattern = ["^https?:\\/\\/", "^https?://"]
+        .iter()
+        .find_map(|prefix| pattern.strip_prefix(prefix))
+        .map(|rest| format!("^{}", rest))
+        .unwrap_or(pattern.to_string());
+    if let Some(host) = ["\\/.*", "\\/"]
+        .iter()
+        .find_map(|suffix| pattern.strip_suffix(suffix))
+    {
+        pattern = format!("{}$", host);
+    }
+    // [^\/] 在域名里等同于任意字符, 其它的 / 说明还在匹配路径
+    if pattern.replace("[^\\/]", "").contains('/') || Regex::new(&pattern).is_err() {
+        return None;
+    }
+    Some(format!("regexp:{}", pattern))
+}
+
+/* 带通配符和 ip 的规则转换不了 */
+fn valid_host(host: &str) -> bool {
+    host.contains('.')
+        && host.parse::<IpAddr>().is_err()
+        && host.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
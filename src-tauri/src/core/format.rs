@@ -0,0 +1,175 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/* 和 xray 一样支持的配置文件格式, jsonc 按带注释的 json 读 */
+static CONFIG_EXTENSIONS: &[&str] = &["json", "jsonc", "yaml", "yml", "toml"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    // 允许 //, /* */ 和 # 注释
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl ConfigFormat {
+    /* 按扩展名判断格式 */
+    pub fn of(path: &Path) -> Option<ConfigFormat> {
+        match path.extension()?.to_str()? {
+            "json" | "jsonc" => Some(ConfigFormat::Json),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            _ => None,
+        }
+    }
+
+    pub fn is_config_file(path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| CONFIG_EXTENSIONS.contains(&extension))
+    }
+
+    pub fn parse(&self, text: &str) -> Result<Value> {
+        Ok(match self {
+            ConfigFormat::Json => serde_json::from_str(strip_comments(text).as_str())?,
+            ConfigFormat::Yaml => serde_yaml::from_str(text)?,
+            ConfigFormat::Toml => toml::from_str(text)?,
+        })
+    }
+}
+
+/* 按扩展名读出任意格式的配置 */
+pub fn read_config(path: &Path) -> Result<Value> {
+    let format = ConfigFormat::of(path).ok_or(anyhow::anyhow!(
+        "unsupported config file {}",
+        path.display()
+    ))?;
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    format
+        .parse(text.as_str())
+        .with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/* app 里修改时整个文件重新写成 json, 注释和其它格式会丢掉, 这些文件只能用编辑器修改 */
+pub fn ensure_editable(path: &Path) -> Result<()> {
+    if path.extension() != Some("json".as_ref()) {
+        anyhow::bail!(
+            "{} is not a .json file, edit it with a text editor",
+            path.display()
+        );
+    }
+    if !path.exists() {
+        return Ok(());
+    }
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    if strip_comments(text.as_str()) != text {
+        anyhow::bail!(
+            "{} has comments, edit it with a text editor to keep them",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/* 暂存目录里的配置都转成不带注释的 .json, 后面的步骤只需要处理 json */
+pub fn normalize_dir(confdir: &Path) -> Result<()> {
+    for entry in fs::read_dir(confdir)? {
+        let path = entry?.path();
+        if !path.is_file() || !ConfigFormat::is_config_file(&path) {
+            continue;
+        }
+        let target = path.with_extension("json");
+        if target != path && target.exists() {
+            anyhow::bail!("{} conflicts with {}", path.display(), target.display());
+        }
+        let value = read_config(&path)?;
+        fs::write(&target, serde_json::to_string_pretty(&value)?)?;
+        if target != path {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/* 去掉 json 里字符串外的注释, 换行保留, 出错时行号不变 */
+pub fn strip_comments(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            result.push(c);
+            match c {
+                '\\' => {
+                    if let Some(escaped) = chars.next() {
+                        result.push(escaped);
+                    }
+                }
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                result.push(c);
+            }
+            ('/', Some('/')) | ('#', _) => {
+                while chars.peek().is_some_and(|next| *next != '\n') {
+                    chars.next();
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut last = '\0';
+                for next in chars.by_ref() {
+                    if next == '\n' {
+                        result.push('\n');
+                    }
+                    if last == '*' && next == '/' {
+                        break;
+                    }
+                    last = next;
+                }
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_comments_keeps_markers_inside_strings() {
+        let text = r#"{"url": "http://a.com/#top", "note": "/* not a comment */"} // tail"#;
+        assert_eq!(
+            strip_comments(text),
+            r#"{"url": "http://a.com/#top", "note": "/* not a comment */"} "#
+        );
+    }
+
+    #[test]
+    fn strip_comments_handles_escaped_quotes() {
+        let text = r#"{"a": "say \"// hi\" # x", "b": "\\"} # comment"#;
+        assert_eq!(
+            strip_comments(text),
+            r#"{"a": "say \"// hi\" # x", "b": "\\"} "#
+        );
+        let value = ConfigFormat::Json.parse(text).unwrap();
+        assert_eq!(value["a"], r#"say "// hi" # x"#);
+        assert_eq!(value["b"], "\\");
+    }
+
+    #[test]
+    fn strip_comments_keeps_line_numbers() {
+        assert_eq!(strip_comments("1 /* a\nb */ 2\n# c\n3"), "1 \n 2\n\n3");
+    }
+}
@@ -0,0 +1,592 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Component, Path, PathBuf};
+
+use super::path::AppPath;
+
+/* geosite.dat / geoip.dat 读取, 格式见 xray common/router/config.proto */
+
+pub static GEOSITE_FILE: &str = "geosite.dat";
+pub static GEOIP_FILE: &str = "geoip.dat";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DomainType {
+    // 关键字
+    Plain,
+    Regex,
+    // 域名及子域名
+    Domain,
+    Full,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeoDomain {
+    pub domain_type: DomainType,
+    pub value: String,
+    pub attributes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    pub ip: IpAddr,
+    pub prefix: u8,
+}
+
+/* 同一次查询里的 regexp 只编译一次, 编译失败的也记下 */
+#[derive(Debug, Default)]
+pub struct RegexCache {
+    compiled: HashMap<String, Option<Regex>>,
+}
+
+/* 分类和条目数 */
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeoCategory {
+    pub code: String,
+    pub count: usize,
+}
+
+/* 包含某个域名的 geosite 分类和命中的条目 */
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeoSiteMatch {
+    pub code: String,
+    pub domain: GeoDomain,
+}
+
+pub struct GeoData {}
+
+impl GeoData {
+    /* 资源目录下的文件, 只允许文件名 */
+    pub fn asset_path(file: &str) -> Result<PathBuf> {
+        let relative = Path::new(file);
+        let mut components = relative.components();
+        let is_file_name =
+            matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+        if !is_file_name {
+            anyhow::bail!("invalid asset file {}", file);
+        }
+        Ok(AppPath::xray_preset_asset_dir()?.join(relative))
+    }
+
+    /* 读取 geosite 文件里的一个分类, code 不区分大小写 */
+    pub fn load_site(file: &str, code: &str) -> Result<Vec<GeoDomain>> {
+        let data = GeoData::read(file)?;
+        GeoData::decode_site(GeoData::entry(&data, file, code)?)
+    }
+
+    /* 读取 geoip 文件里的一个分类, 返回 (cidr 列表, 是否反向匹配) */
+    pub fn load_ip(file: &str, code: &str) -> Result<(Vec<Cidr>, bool)> {
+        let data = GeoData::read(file)?;
+        GeoData::decode_ip(GeoData::entry(&data, file, code)?)
+    }
+
+    /* 列出所有分类, geosite 和 geoip 的条目都在字段 2 */
+    pub fn list_categories(file: &str) -> Result<Vec<GeoCategory>> {
+        let data = GeoData::read(file)?;
+        let mut categories = Vec::new();
+        for (code, entry) in GeoData::entries(&data)? {
+            let mut count = 0;
+            for field in Fields::new(entry) {
+                if field?.0 == 2 {
+                    count += 1;
+                }
+            }
+            categories.push(GeoCategory { code, count });
+        }
+        categories.sort_by(|a, b| a.code.cmp(&b.code));
+        Ok(categories)
+    }
+
+    /* 包含这个域名的分类 */
+    pub fn find_site(file: &str, domain: &str) -> Result<Vec<GeoSiteMatch>> {
+        let domain = domain.trim().trim_end_matches('.').to_lowercase();
+        let data = GeoData::read(file)?;
+        let mut regexes = RegexCache::default();
+
+        let mut matches = Vec::new();
+        for (code, entry) in GeoData::entries(&data)? {
+            for geo_domain in GeoData::decode_site(entry)? {
+                if geo_domain.matches(&domain, &mut regexes) {
+                    matches.push(GeoSiteMatch {
+                        code: code.clone(),
+                        domain: geo_domain,
+                    });
+                }
+            }
+        }
+        Ok(matches)
+    }
+
+    /* 包含这个 ip 的分类, 反向匹配的分类按 xray 的规则处理 */
+    pub fn find_ip(file: &str, ip: IpAddr) -> Result<Vec<String>> {
+        let data = GeoData::read(file)?;
+
+        let mut codes = Vec::new();
+        for (code, entry) in GeoData::entries(&data)? {
+            let (cidrs, reverse_match) = GeoData::decode_ip(entry)?;
+            if cidrs.iter().any(|cidr| cidr.contains(&ip)) != reverse_match {
+                codes.push(code);
+            }
+        }
+        Ok(codes)
+    }
+
+    fn read(file: &str) -> Result<Vec<u8>> {
+        let path = GeoData::asset_path(file)?;
+        fs::read(&path).with_context(|| format!("failed to read {}", path.display()))
+    }
+
+    fn decode_site(entry: &[u8]) -> Result<Vec<GeoDomain>> {
+        let mut domains = Vec::new();
+        for field in Fields::new(entry) {
+            if let (2, Value::Bytes(domain)) = field? {
+                domains.push(GeoDomain::decode(domain)?);
+            }
+        }
+        Ok(domains)
+    }
+
+    fn decode_ip(entry: &[u8]) -> Result<(Vec<Cidr>, bool)> {
+        let mut cidrs = Vec::new();
+        let mut reverse_match = false;
+        for field in Fields::new(entry) {
+            match field? {
+                (2, Value::Bytes(cidr)) => cidrs.push(Cidr::decode(cidr)?),
+                (3, Value::Varint(reverse)) => reverse_match = reverse != 0,
+                _ => {}
+            }
+        }
+        Ok((cidrs, reverse_match))
+    }
+
+    fn entry<'a>(data: &'a [u8], file: &str, code: &str) -> Result<&'a [u8]> {
+        GeoData::find_entry(data, code)?.ok_or(anyhow::anyhow!("{} not found in {}", code, file))
+    }
+
+    /* 在 GeoSiteList/GeoIPList 里按 country_code 找到分类, 只解析需要的那个 */
+    fn find_entry<'a>(data: &'a [u8], code: &str) -> Result<Option<&'a [u8]>> {
+        for field in Fields::new(data) {
+            if let (1, Value::Bytes(entry)) = field? {
+                if GeoData::entry_code(entry)?.eq_ignore_ascii_case(code) {
+                    return Ok(Some(entry));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /* 所有分类, code 转成小写和规则里的写法一致 */
+    fn entries(data: &[u8]) -> Result<Vec<(String, &[u8])>> {
+        let mut entries = Vec::new();
+        for field in Fields::new(data) {
+            if let (1, Value::Bytes(entry)) = field? {
+                entries.push((GeoData::entry_code(entry)?.to_lowercase(), entry));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn entry_code(entry: &[u8]) -> Result<String> {
+        for field in Fields::new(entry) {
+            if let (1, Value::Bytes(code)) = field? {
+                return Ok(String::from_utf8_lossy(code).to_string());
+            }
+        }
+        Ok(String::new())
+    }
+}
+
+impl GeoDomain {
+    fn decode(data: &[u8]) -> Result<GeoDomain> {
+        let mut domain = GeoDomain {
+            domain_type: DomainType::Plain,
+            value: String::new(),
+            attributes: Vec::new(),
+        };
+        for field in Fields::new(data) {
+            match field? {
+                (1, Value::Varint(domain_type)) => {
+                    domain.domain_type = match domain_type {
+                        1 => DomainType::Regex,
+                        2 => DomainType::Domain,
+                        3 => DomainType::Full,
+                        _ => DomainType::Plain,
+                    }
+                }
+                (2, Value::Bytes(value)) => {
+                    domain.value = String::from_utf8_lossy(value).to_string()
+                }
+                (3, Value::Bytes(attribute)) => {
+                    for attribute_field in Fields::new(attribute) {
+                        if let (1, Value::Bytes(key)) = attribute_field? {
+                            domain
+                                .attributes
+                                .push(String::from_utf8_lossy(key).to_string());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(domain)
+    }
+
+    /* 按 xray 的规则匹配域名 */
+    pub fn matches(&self, domain: &str, regexes: &mut RegexCache) -> bool {
+        match self.domain_type {
+            DomainType::Plain => domain.contains(self.value.as_str()),
+            DomainType::Full => domain == self.value,
+            DomainType::Domain => is_subdomain(domain, &self.value),
+            DomainType::Regex => regexes.get(&self.value).is_ok_and(|re| re.is_match(domain)),
+        }
+    }
+}
+
+impl RegexCache {
+    pub fn get(&mut self, pattern: &str) -> Result<&Regex> {
+        self.compiled
+            .entry(pattern.to_string())
+            .or_insert_with(|| Regex::new(pattern).ok())
+            .as_ref()
+            .ok_or(anyhow::anyhow!("invalid regexp {}", pattern))
+    }
+}
+
+impl Cidr {
+    fn decode(data: &[u8]) -> Result<Cidr> {
+        let mut ip_bytes: &[u8] = &[];
+        let mut prefix = 0;
+        for field in Fields::new(data) {
+            match field? {
+                (1, Value::Bytes(ip)) => ip_bytes = ip,
+                (2, Value::Varint(value)) => prefix = value as u8,
+                _ => {}
+            }
+        }
+        let ip = match ip_bytes.len() {
+            4 => IpAddr::from(<[u8; 4]>::try_from(ip_bytes)?),
+            16 => IpAddr::from(<[u8; 16]>::try_from(ip_bytes)?),
+            len => anyhow::bail!("invalid cidr ip length {}", len),
+        };
+        Ok(Cidr { ip, prefix })
+    }
+
+    /* 解析 1.2.3.0/24 或单个 ip */
+    pub fn parse(text: &str) -> Option<Cidr> {
+        let (ip, prefix) = text.split_once('/').unwrap_or((text, ""));
+        let ip: IpAddr = ip.trim().parse().ok()?;
+        let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix = if prefix.is_empty() {
+            max_prefix
+        } else {
+            prefix.trim().parse().ok()?
+        };
+        (prefix <= max_prefix).then_some(Cidr { ip, prefix })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let (network, ip, bits) = match (self.ip, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                (u32::from(network) as u128, u32::from(*ip) as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (u128::from(network), u128::from(*ip), 128),
+            _ => return false,
+        };
+        let prefix = u32::from(self.prefix.min(bits));
+        if prefix == 0 {
+            return true;
+        }
+        let shift = bits as u32 - prefix;
+        (network >> shift) == (ip >> shift)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.ip, self.prefix)
+    }
+}
+
+/* domain 是 parent 本身或它的子域名 */
+pub fn is_subdomain(domain: &str, parent: &str) -> bool {
+    domain == parent
+        || (domain.len() > parent.len()
+            && domain.ends_with(parent)
+            && domain.as_bytes()[domain.len() - parent.len() - 1] == b'.')
+}
+
+/* protobuf 解码, 只处理用到的线格式 */
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+struct Fields<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Fields<'a> {
+        Fields { data, pos: 0 }
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or(anyhow::anyhow!("unexpected end of data"))?;
+            self.pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        anyhow::bail!("invalid varint")
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(anyhow::anyhow!("unexpected end of data"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn field(&mut self) -> Result<(u64, Value<'a>)> {
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                Value::Fixed
+            }
+            wire_type => anyhow::bail!("unsupported wire type {}", wire_type),
+        };
+        Ok((key >> 3, value))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, Value<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            // 出错后不再继续解析
+            self.pos = self.data.len();
+        }
+        Some(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 手工编码 protobuf, 字段号和 xray common/router/config.proto 一致
+    fn bytes_field(number: u8, data: &[u8]) -> Vec<u8> {
+        let mut field = vec![number << 3 | 2, data.len() as u8];
+        field.extend_from_slice(data);
+        field
+    }
+
+    fn varint_field(number: u8, value: u8) -> Vec<u8> {
+        vec![number << 3, value]
+    }
+
+    fn domain(domain_type: u8, value: &str, attributes: &[&str]) -> Vec<u8> {
+        let mut domain = Vec::new();
+        // 0 是默认值, 和 protobuf 一样不写
+        if domain_type != 0 {
+            domain.extend(varint_field(1, domain_type));
+        }
+        domain.extend(bytes_field(2, value.as_bytes()));
+        for attribute in attributes {
+            domain.extend(bytes_field(
+                3,
+                &[bytes_field(1, attribute.as_bytes()), varint_field(2, 1)].concat(),
+            ));
+        }
+        bytes_field(2, &domain)
+    }
+
+    fn cidr(ip: &[u8], prefix: u8) -> Vec<u8> {
+        bytes_field(2, &[bytes_field(1, ip), varint_field(2, prefix)].concat())
+    }
+
+    fn geosite_list() -> Vec<u8> {
+        let cn = [
+            bytes_field(1, b"CN"),
+            domain(2, "example.cn", &["ads"]),
+            domain(3, "full.cn", &[]),
+            domain(0, "keyword", &[]),
+            domain(1, "^re\\.", &[]),
+        ]
+        .concat();
+        let google = [bytes_field(1, b"GOOGLE"), domain(2, "google.com", &[])].concat();
+        [bytes_field(1, &cn), bytes_field(1, &google)].concat()
+    }
+
+    #[test]
+    fn decodes_geosite_entries() {
+        let data = geosite_list();
+        let entries = GeoData::entries(&data).unwrap();
+        let codes: Vec<&str> = entries.iter().map(|(code, _)| code.as_str()).collect();
+        assert_eq!(codes, ["cn", "google"]);
+        assert!(GeoData::find_entry(&data, "google").unwrap().is_some());
+        assert!(GeoData::find_entry(&data, "private").unwrap().is_none());
+
+        let domains =
+            GeoData::decode_site(GeoData::entry(&data, "geosite.dat", "cn").unwrap()).unwrap();
+        let decoded: Vec<(DomainType, &str, Vec<&str>)> = domains
+            .iter()
+            .map(|domain| {
+                (
+                    domain.domain_type,
+                    domain.value.as_str(),
+                    domain.attributes.iter().map(|attr| attr.as_str()).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                (DomainType::Domain, "example.cn", vec!["ads"]),
+                (DomainType::Full, "full.cn", vec![]),
+                (DomainType::Plain, "keyword", vec![]),
+                (DomainType::Regex, "^re\\.", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_geoip_entry() {
+        let ipv6 = [0x24, 0x0e, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let entry = [
+            bytes_field(1, b"CN"),
+            cidr(&[1, 0, 1, 0], 24),
+            cidr(&ipv6, 20),
+            varint_field(3, 1),
+        ]
+        .concat();
+        assert_eq!(GeoData::entry_code(&entry).unwrap(), "CN");
+        let (cidrs, reverse_match) = GeoData::decode_ip(&entry).unwrap();
+        assert_eq!(
+            cidrs,
+            [
+                Cidr::parse("1.0.1.0/24").unwrap(),
+                Cidr::parse("240e::/20").unwrap()
+            ]
+        );
+        assert!(reverse_match);
+
+        let (_, reverse_match) = GeoData::decode_ip(&bytes_field(1, b"US")).unwrap();
+        assert!(!reverse_match);
+        assert!(GeoData::decode_ip(&cidr(&[1, 0, 1], 24)).is_err());
+    }
+
+    #[test]
+    fn geo_domain_matches() {
+        let data = geosite_list();
+        let domains =
+            GeoData::decode_site(GeoData::entry(&data, "geosite.dat", "CN").unwrap()).unwrap();
+        let mut regexes = RegexCache::default();
+        let mut matches = |domain: &str| -> Vec<bool> {
+            domains
+                .iter()
+                .map(|geo_domain| geo_domain.matches(domain, &mut regexes))
+                .collect()
+        };
+        assert_eq!(matches("www.example.cn"), [true, false, false, false]);
+        assert_eq!(matches("full.cn"), [false, true, false, false]);
+        assert_eq!(matches("www.full.cn"), [false, false, false, false]);
+        assert_eq!(matches("a.keyword.com"), [false, false, true, false]);
+        assert_eq!(matches("re.com"), [false, false, false, true]);
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let cidr = Cidr::parse("10.1.0.0/16").unwrap();
+        assert!(cidr.contains(&"10.1.255.1".parse().unwrap()));
+        assert!(!cidr.contains(&"10.2.0.1".parse().unwrap()));
+        assert!(!cidr.contains(&"::ffff:10.1.0.1".parse().unwrap()));
+
+        let cidr = Cidr::parse("2001:db8::/32").unwrap();
+        assert!(cidr.contains(&"2001:db8:ffff::1".parse().unwrap()));
+        assert!(!cidr.contains(&"2001:db9::1".parse().unwrap()));
+
+        assert!(Cidr::parse("0.0.0.0/0")
+            .unwrap()
+            .contains(&"8.8.8.8".parse().unwrap()));
+        let single = Cidr::parse("8.8.8.8").unwrap();
+        assert_eq!(single.prefix, 32);
+        assert!(single.contains(&"8.8.8.8".parse().unwrap()));
+        assert!(!single.contains(&"8.8.8.9".parse().unwrap()));
+        assert!(Cidr::parse("1.2.3.4/33").is_none());
+    }
+
+    #[test]
+    fn subdomain() {
+        assert!(is_subdomain("example.com", "example.com"));
+        assert!(is_subdomain("a.b.example.com", "example.com"));
+        assert!(!is_subdomain("badexample.com", "example.com"));
+        assert!(!is_subdomain("com", "example.com"));
+    }
+
+    #[test]
+    fn varint_decodes_multi_byte_values() {
+        let mut fields = Fields::new(&[0x96, 0x01]);
+        assert_eq!(fields.varint().unwrap(), 150);
+    }
+
+    #[test]
+    fn varint_fails_on_truncated_data() {
+        assert!(Fields::new(&[]).varint().is_err());
+        assert!(Fields::new(&[0x96]).varint().is_err());
+        assert!(Fields::new(&[0xff; 10]).varint().is_err());
+    }
+
+    #[test]
+    fn fields_stop_after_truncated_field() {
+        // 字段 1, 长度 5, 只有 1 个字节
+        let mut fields = Fields::new(&[0x0a, 0x05, 0x61, 0x08, 0x01]);
+        assert!(fields.next().is_some_and(|field| field.is_err()));
+        assert!(fields.next().is_none());
+        // 长度本身被截断
+        let mut fields = Fields::new(&[0x0a, 0x80]);
+        assert!(fields.next().is_some_and(|field| field.is_err()));
+        assert!(fields.next().is_none());
+    }
+
+    #[test]
+    fn truncated_entry_is_an_error() {
+        assert!(GeoData::entry_code(&[0x0a, 0x02, b'c']).is_err());
+        assert!(GeoData::decode_site(&[0x12, 0x04, 0x08]).is_err());
+    }
+}
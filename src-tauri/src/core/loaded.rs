@@ -0,0 +1,123 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use tauri::AppHandle;
+
+use super::config::IConfig;
+use super::format::read_config;
+use super::outbound::{main_index, outbound_tags, outbounds_of, retag_outbounds, Outbounds};
+
+/* 预设和当前 outbound 用到的 tag, 加载的 outbound 不能占用 */
+static RESERVED_TAGS: &[&str] = &["proxy", "direct", "block"];
+
+/* 代理链 dialer 的 tag 前缀, 加载的 tag 加上 "-" 后也不能和它重复 */
+static RESERVED_PREFIX: &str = "dialer";
+
+/* 额外加载的 outbound, 用自己的 tag 合并进暂存配置, 路由规则直接指向 tag */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LoadedOutbound {
+    pub id: String,
+    // 主出站的 tag, 文件里其它出站的 tag 加上 "{tag}-" 前缀
+    pub tag: String,
+}
+
+pub struct LoadedOutbounds {}
+
+impl LoadedOutbounds {
+    pub fn list(app: &AppHandle) -> Vec<LoadedOutbound> {
+        IConfig::snapshot(app)
+            .map(|config| config.loaded_outbounds)
+            .unwrap_or_default()
+    }
+
+    /* 整体替换加载的 outbound */
+    pub fn set(app: &AppHandle, loaded: Vec<LoadedOutbound>) -> Result<()> {
+        let mut tags = HashSet::new();
+        for item in &loaded {
+            LoadedOutbounds::validate_tag(&item.tag)?;
+            if !tags.insert(item.tag.as_str()) {
+                anyhow::bail!("tag {} is used twice", item.tag);
+            }
+            if !Outbounds::resolve(&item.id)?.is_file() {
+                anyhow::bail!("outbound {} not found", item.id);
+            }
+        }
+        IConfig::update(app, |config| config.loaded_outbounds = loaded)
+    }
+
+    /* outbound 改名时同步 */
+    pub fn rename_outbound(loaded: &mut [LoadedOutbound], id: &str, new_id: &str) {
+        for item in loaded.iter_mut().filter(|item| item.id == id) {
+            item.id = new_id.to_string();
+        }
+    }
+
+    /* 合并成暂存的 outbound, 没有加载时返回 None
+     * tag 不能和暂存目录里已有的出站重复, 包括 dns, 反向代理和分片生成的, 所以最后暂存 */
+    pub fn render(loaded: &[LoadedOutbound], confdir: &Path) -> Result<Option<Value>> {
+        if loaded.is_empty() {
+            return Ok(None);
+        }
+
+        let mut staged = Vec::new();
+        let mut used = staged_tags(confdir)?;
+        for item in loaded {
+            LoadedOutbounds::validate_tag(&item.tag)?;
+            let mut outbounds = outbounds_of(&Outbounds::read_staged(&item.id)?, &item.id)?;
+            let main_tag = outbound_tags(&outbounds)[main_index(&outbounds, "proxy")].clone();
+            retag_outbounds(&mut outbounds, |tag| match tag == main_tag {
+                true => item.tag.clone(),
+                false => format!("{}-{}", item.tag, tag),
+            });
+
+            for tag in outbound_tags(&outbounds) {
+                if !used.insert(tag.clone()) {
+                    anyhow::bail!("outbound {} duplicates tag {}", item.id, tag);
+                }
+            }
+            staged.extend(outbounds);
+        }
+        Ok(Some(json!({ "outbounds": staged })))
+    }
+
+    fn validate_tag(tag: &str) -> Result<()> {
+        let valid = !tag.is_empty()
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            anyhow::bail!("invalid tag {}", tag);
+        }
+        if RESERVED_TAGS.contains(&tag) || tag.starts_with(RESERVED_PREFIX) {
+            anyhow::bail!("tag {} is reserved", tag);
+        }
+        Ok(())
+    }
+}
+
+/* 暂存目录里所有出站的 tag */
+fn staged_tags(confdir: &Path) -> Result<HashSet<String>> {
+    let mut tags = HashSet::new();
+    for entry in fs::read_dir(confdir)? {
+        let path = entry?.path();
+        if path.extension() != Some("json".as_ref()) {
+            continue;
+        }
+        let value = read_config(&path)?;
+        if let Some(outbounds) = value
+            .get("outbounds")
+            .and_then(|outbounds| outbounds.as_array())
+        {
+            tags.extend(
+                outbounds
+                    .iter()
+                    .filter_map(|outbound| outbound.get("tag")?.as_str())
+                    .map(|tag| tag.to_string()),
+            );
+        }
+    }
+    Ok(tags)
+}
@@ -0,0 +1,80 @@
+use anyhow::Result;
+use flate2::{write::GzEncoder, Compression};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tauri::AppHandle;
+
+use super::{config::IConfig, path::AppPath};
+
+pub struct Logs {}
+
+impl Logs {
+    /* 清理日志目录: 按数量/时间删除轮转出来的旧日志, 可选gzip压缩 */
+    pub fn cleanup(app: &AppHandle, app_name: &str) -> Result<()> {
+        let log_config = IConfig::log_config(app).unwrap_or_default();
+
+        let mut rotated: Vec<(PathBuf, SystemTime)> = fs::read_dir(AppPath::app_log_dir()?)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && is_rotated_log(path, app_name))
+            .filter_map(|path| {
+                let modified = path.metadata().and_then(|meta| meta.modified()).ok()?;
+                Some((path, modified))
+            })
+            .collect();
+        // 新的在前
+        rotated.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+
+        let max_age = Duration::from_secs(log_config.max_age_days * 24 * 60 * 60);
+        let now = SystemTime::now();
+        for (index, (path, modified)) in rotated.into_iter().enumerate() {
+            let expired = log_config.max_age_days > 0
+                && now
+                    .duration_since(modified)
+                    .map(|age| age > max_age)
+                    .unwrap_or(false);
+
+            if index >= log_config.keep_files || expired {
+                log::debug!(target: "app", "remove old log {}", path.display());
+                // 单个文件失败时继续清理其它的
+                if let Err(err) = fs::remove_file(&path) {
+                    log::warn!(target: "app", "failed to remove log {}: {err}", path.display());
+                }
+                continue;
+            }
+
+            if log_config.gzip && path.extension() == Some("log".as_ref()) {
+                if let Err(err) = Logs::gzip(&path, modified) {
+                    log::warn!(target: "app", "failed to gzip log {}: {err}", path.display());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn gzip(path: &Path, modified: SystemTime) -> Result<()> {
+        let gz_path = path.with_extension("log.gz");
+        log::debug!(target: "app", "gzip log {}", path.display());
+
+        let mut reader = File::open(path)?;
+        let mut encoder = GzEncoder::new(File::create(&gz_path)?, Compression::default());
+        io::copy(&mut reader, &mut encoder)?;
+        // 保留原文件的修改时间, 以免压缩后按时间清理失效
+        encoder.finish()?.set_modified(modified)?;
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
+
+/* tauri-plugin-log 轮转出来的日志, {app}_{时间}.log, 压缩后是 .log.gz; 正在写入的 {app}.log 和其它文件不动 */
+fn is_rotated_log(path: &Path, app_name: &str) -> bool {
+    path.file_name()
+        .and_then(|file_name| file_name.to_str())
+        .and_then(|file_name| file_name.strip_prefix(app_name))
+        .and_then(|rest| rest.strip_prefix('_'))
+        .is_some_and(|rest| rest.ends_with(".log") || rest.ends_with(".log.gz"))
+}
@@ -7,3 +7,33 @@ pub mod xray;
 pub mod config;
 
 pub mod sys;
+
+pub mod logs;
+
+pub mod store;
+
+pub mod state;
+
+pub mod profile;
+
+pub mod outbound;
+
+pub mod schema;
+
+pub mod routing;
+
+pub mod chain;
+pub mod check;
+pub mod dns;
+pub mod domain_list;
+pub mod format;
+pub mod geodata;
+pub mod loaded;
+pub mod options;
+pub mod overlay;
+pub mod reverse;
+pub mod server;
+pub mod simulate;
+pub mod template;
+pub mod variable;
+pub mod watcher;
@@ -0,0 +1,190 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::outbound::main_index;
+
+/* xray 支持的 tls 指纹 */
+static FINGERPRINTS: &[&str] = &[
+    "chrome",
+    "firefox",
+    "safari",
+    "ios",
+    "android",
+    "edge",
+    "360",
+    "qq",
+    "random",
+    "randomized",
+];
+
+/* xray 的 mux 并发数, -1 表示 tcp 不走 mux, 0 为默认值 */
+const MUX_CONCURRENCY_RANGE: std::ops::RangeInclusive<i16> = -1..=1024;
+
+/* tls 分片, 通过 freedom 出站的 fragment 和 dialerProxy 实现 */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FragmentOptions {
+    // tlshello 或 1-3 这样的包序号范围
+    pub packets: String,
+    // 分片长度范围, 如 100-200
+    pub length: String,
+    // 分片间隔毫秒范围, 如 10-20
+    pub interval: String,
+}
+
+/* 每个 outbound 的选项, 暂存时覆盖到主出站上, 为空的不修改原文件的设置 */
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OutboundOptions {
+    pub mux: Option<bool>,
+    pub mux_concurrency: Option<i16>,
+    pub fragment: Option<FragmentOptions>,
+    pub fingerprint: Option<String>,
+    pub tcp_fast_open: Option<bool>,
+}
+
+impl OutboundOptions {
+    pub fn is_empty(&self) -> bool {
+        *self == OutboundOptions::default()
+    }
+
+    /* config 是要覆盖的 outbound 文件, 指纹只能用在 tls 或 reality 上 */
+    pub fn validate(&self, config: &Value) -> Result<()> {
+        if let Some(concurrency) = self.mux_concurrency {
+            if !MUX_CONCURRENCY_RANGE.contains(&concurrency) {
+                anyhow::bail!("mux concurrency {} is not in -1..=1024", concurrency);
+            }
+        }
+        if let Some(fingerprint) = &self.fingerprint {
+            if !FINGERPRINTS.contains(&fingerprint.as_str()) {
+                anyhow::bail!("unknown fingerprint {}", fingerprint);
+            }
+            let main = config
+                .get("outbounds")
+                .and_then(|outbounds| outbounds.as_array())
+                .filter(|outbounds| !outbounds.is_empty())
+                .map(|outbounds| &outbounds[main_index(outbounds, "proxy")]);
+            if main
+                .and_then(|main| main.get("streamSettings"))
+                .and_then(security_settings_key)
+                .is_none()
+            {
+                anyhow::bail!("fingerprint requires tls or reality security");
+            }
+        }
+        if let Some(fragment) = &self.fragment {
+            if fragment.packets != "tlshello" && !is_range(&fragment.packets) {
+                anyhow::bail!("invalid fragment packets {}", fragment.packets);
+            }
+            for range in [&fragment.length, &fragment.interval] {
+                if !is_range(range) {
+                    anyhow::bail!("invalid fragment range {}", range);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* 覆盖到 outbound 文件的主出站上, 分片时加一个 freedom 出站 */
+    pub fn apply(&self, config: &mut Value) {
+        let Some(outbounds) = config
+            .get_mut("outbounds")
+            .and_then(|outbounds| outbounds.as_array_mut())
+            .filter(|outbounds| !outbounds.is_empty())
+        else {
+            return;
+        };
+        let index = main_index(outbounds, "proxy");
+        let main = &mut outbounds[index];
+        let main_tag = main
+            .get("tag")
+            .and_then(|tag| tag.as_str())
+            .unwrap_or("proxy")
+            .to_string();
+
+        if self.mux.is_some() || self.mux_concurrency.is_some() {
+            if !matches!(main.get("mux"), Some(Value::Object(_))) {
+                main["mux"] = json!({});
+            }
+            if let Some(enabled) = self.mux {
+                main["mux"]["enabled"] = Value::Bool(enabled);
+            }
+            if let Some(concurrency) = self.mux_concurrency {
+                main["mux"]["concurrency"] = Value::from(concurrency);
+            }
+        }
+
+        if self.fingerprint.is_none() && self.tcp_fast_open.is_none() && self.fragment.is_none() {
+            return;
+        }
+        if !matches!(main.get("streamSettings"), Some(Value::Object(_))) {
+            main["streamSettings"] = json!({});
+        }
+        let stream_settings = &mut main["streamSettings"];
+
+        // 指纹写到当前使用的 tls 或 reality 设置里, 保存后文件可能改成了别的 security
+        if let Some(fingerprint) = &self.fingerprint {
+            match security_settings_key(stream_settings) {
+                Some(key) => {
+                    if !matches!(stream_settings.get(key), Some(Value::Object(_))) {
+                        stream_settings[key] = json!({});
+                    }
+                    stream_settings[key]["fingerprint"] = Value::from(fingerprint.clone());
+                }
+                None => log::warn!(
+                    target: "app",
+                    "[options]: {main_tag} does not use tls or reality, skip fingerprint"
+                ),
+            }
+        }
+
+        if self.tcp_fast_open.is_none() && self.fragment.is_none() {
+            return;
+        }
+        if !matches!(stream_settings.get("sockopt"), Some(Value::Object(_))) {
+            stream_settings["sockopt"] = json!({});
+        }
+        if let Some(tcp_fast_open) = self.tcp_fast_open {
+            stream_settings["sockopt"]["tcpFastOpen"] = Value::Bool(tcp_fast_open);
+        }
+
+        // 已经有前置代理时分片加不上
+        let Some(fragment) = &self.fragment else {
+            return;
+        };
+        if stream_settings["sockopt"].get("dialerProxy").is_some() {
+            log::warn!(target: "app", "[options]: {main_tag} already has a dialerProxy, skip fragment");
+            return;
+        }
+        let fragment_tag = format!("{}-fragment", main_tag);
+        stream_settings["sockopt"]["dialerProxy"] = Value::from(fragment_tag.clone());
+        outbounds.push(json!({
+            "tag": fragment_tag,
+            "protocol": "freedom",
+            "settings": {
+                "fragment": {
+                    "packets": fragment.packets,
+                    "length": fragment.length,
+                    "interval": fragment.interval
+                }
+            }
+        }));
+    }
+}
+
+fn security_settings_key(stream_settings: &Value) -> Option<&'static str> {
+    match stream_settings.get("security").and_then(|s| s.as_str()) {
+        Some("reality") => Some("realitySettings"),
+        Some("tls") => Some("tlsSettings"),
+        _ => None,
+    }
+}
+
+/* 10 或 10-20 */
+fn is_range(text: &str) -> bool {
+    let (start, end) = text.split_once('-').unwrap_or((text, text));
+    match (start.trim().parse::<u32>(), end.trim().parse::<u32>()) {
+        (Ok(start), Ok(end)) => start <= end,
+        _ => false,
+    }
+}
@@ -0,0 +1,532 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Component, Path, PathBuf};
+use tauri::AppHandle;
+
+use super::chain::Chains;
+use super::config::IConfig;
+use super::format::{ensure_editable, read_config, ConfigFormat};
+use super::loaded::LoadedOutbounds;
+use super::options::OutboundOptions;
+use super::path::AppPath;
+use super::profile::Profile;
+use super::reverse::ReverseProxies;
+use super::schema::OutboundConfig;
+use super::store::Store;
+use super::variable::Variables;
+use super::xray::Xray;
+
+/* outbound 元数据文件后缀, 和 outbound 文件放在一起 */
+pub static META_SUFFIX: &str = ".meta.json";
+
+/* 子目录最多递归层数 */
+const MAX_DEPTH: usize = 4;
+
+/* 常见节点国家代码, 用于从服务器地址识别 */
+static COUNTRIES: &[&str] = &[
+    "jp", "us", "hk", "sg", "tw", "kr", "uk", "gb", "de", "fr", "nl", "ca", "au", "ru", "in", "tr",
+    "th", "vn", "my", "ph",
+];
+
+/* 和常见英文单词一样的代码, 只按顶级域名识别, 如 my.vps.example.com 不是马来西亚 */
+static WORD_COUNTRIES: &[&str] = &["in", "my", "tr", "th"];
+
+/* outbound 元数据 */
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OutboundMeta {
+    pub display_name: Option<String>,
+    pub remark: Option<String>,
+    pub group: Option<String>,
+    pub tags: Vec<String>,
+    // 国家代码, 为空时按服务器地址识别
+    pub country: Option<String>,
+    // 暂存时覆盖到 outbound 上的选项
+    pub options: OutboundOptions,
+}
+
+/* outbound 列表项 */
+#[derive(Debug, Clone, Serialize)]
+pub struct OutboundEntry {
+    pub path: PathBuf,
+    // 相对 outbound 目录的路径, 用 / 分隔, 作为 active_outbound 和菜单 id
+    pub id: String,
+    // 所在子目录, 每层子目录是一个分组
+    pub folder: Vec<String>,
+    pub file_name: String,
+    pub display_name: String,
+    pub address: Option<String>,
+    pub country: Option<String>,
+    pub meta: OutboundMeta,
+}
+
+impl OutboundEntry {
+    /* 递归扫描 outbound 目录 */
+    pub fn scan(root: &Path) -> Result<Vec<OutboundEntry>> {
+        let mut outbounds = Vec::new();
+        OutboundEntry::scan_dir(root, root, 0, &mut outbounds)?;
+        Ok(outbounds)
+    }
+
+    fn scan_dir(
+        root: &Path,
+        dir: &Path,
+        depth: usize,
+        outbounds: &mut Vec<OutboundEntry>,
+    ) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if depth + 1 < MAX_DEPTH {
+                    OutboundEntry::scan_dir(root, &path, depth + 1, outbounds)?;
+                }
+            } else if path.is_file()
+                && ConfigFormat::is_config_file(&path)
+                && !OutboundEntry::is_meta_file(&path)
+            {
+                if let Some(outbound) = OutboundEntry::load(root, path) {
+                    outbounds.push(outbound);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(root: &Path, path: PathBuf) -> Option<OutboundEntry> {
+        let mut components: Vec<String> = path
+            .strip_prefix(root)
+            .ok()?
+            .components()
+            .map(|component| component.as_os_str().to_str().map(|s| s.to_string()))
+            .collect::<Option<Vec<String>>>()?;
+        let id = components.join("/");
+        let file_name = components.pop()?;
+        let folder = components;
+
+        let meta = OutboundEntry::read_meta(&path).unwrap_or_default();
+        let (address, server_name) = OutboundEntry::server(&path);
+
+        let display_name = meta.display_name.clone().unwrap_or_else(|| {
+            path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or(file_name.as_str())
+                .to_string()
+        });
+        let country = meta
+            .country
+            .clone()
+            .map(|country| country.to_lowercase())
+            .or_else(|| address.as_deref().and_then(detect_country))
+            .or_else(|| server_name.as_deref().and_then(detect_country));
+
+        Some(OutboundEntry {
+            path,
+            id,
+            folder,
+            file_name,
+            display_name,
+            address,
+            country,
+            meta,
+        })
+    }
+
+    /* 菜单显示的名字, 带国旗 */
+    pub fn label(&self) -> String {
+        match self.country.as_deref().and_then(country_flag) {
+            Some(flag) => format!("{} {}", flag, self.display_name),
+            None => self.display_name.clone(),
+        }
+    }
+
+    pub fn group(&self) -> Option<&str> {
+        self.meta.group.as_deref()
+    }
+
+    pub fn meta_path(path: &Path) -> Option<PathBuf> {
+        let stem = path.file_stem()?.to_str()?;
+        Some(path.with_file_name(format!("{}{}", stem, META_SUFFIX)))
+    }
+
+    pub fn is_meta_file(path: &Path) -> bool {
+        path.file_name()
+            .and_then(|file_name| file_name.to_str())
+            .is_some_and(|file_name| file_name.ends_with(META_SUFFIX))
+    }
+
+    pub fn read_meta(path: &Path) -> Result<OutboundMeta> {
+        let meta_path =
+            OutboundEntry::meta_path(path).ok_or(anyhow::anyhow!("invalid outbound path"))?;
+        if !meta_path.exists() {
+            return Ok(OutboundMeta::default());
+        }
+        let json_str = fs::read_to_string(meta_path)?;
+        Ok(serde_json::from_str(json_str.as_str())?)
+    }
+
+    pub fn write_meta(path: &Path, meta: &OutboundMeta) -> Result<()> {
+        let meta_path =
+            OutboundEntry::meta_path(path).ok_or(anyhow::anyhow!("invalid outbound path"))?;
+        let json_str = serde_json::to_string_pretty(meta)?;
+        Store::write_atomic(&meta_path, json_str.as_bytes())
+    }
+
+    /* 第一个 outbound 的服务器地址和 tls serverName */
+    fn server(path: &Path) -> (Option<String>, Option<String>) {
+        let outbound = read_config(path)
+            .ok()
+            .and_then(|config| config.get("outbounds")?.get(0).cloned());
+        let Some(outbound) = outbound else {
+            return (None, None);
+        };
+
+        let address = ["vnext", "servers"].iter().find_map(|key| {
+            outbound
+                .get("settings")?
+                .get(key)?
+                .get(0)?
+                .get("address")?
+                .as_str()
+                .map(|address| address.to_string())
+        });
+        let server_name = ["tlsSettings", "realitySettings"].iter().find_map(|key| {
+            outbound
+                .get("streamSettings")?
+                .get(key)?
+                .get("serverName")?
+                .as_str()
+                .map(|server_name| server_name.to_string())
+        });
+
+        (address, server_name)
+    }
+}
+
+/* 从域名里识别国家: 订阅里常见的开头的 jp.example.com, us-01.example.com, hk2.example.com, 或者顶级域名 example.co.jp */
+fn detect_country(address: &str) -> Option<String> {
+    let address = address.trim_end_matches('.').to_lowercase();
+    let labels: Vec<&str> = address.split('.').collect();
+    if labels.len() < 2 || address.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+
+    let leading = labels[0]
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(|c: char| c.is_ascii_digit());
+    if labels.len() > 2 && COUNTRIES.contains(&leading) && !WORD_COUNTRIES.contains(&leading) {
+        return Some(leading.to_string());
+    }
+    let tld = labels[labels.len() - 1];
+    COUNTRIES.contains(&tld).then(|| tld.to_string())
+}
+
+/* 国家代码转国旗 emoji */
+pub fn country_flag(code: &str) -> Option<String> {
+    let code = match code.to_lowercase().as_str() {
+        "uk" => "gb".to_string(),
+        code => code.to_string(),
+    };
+    if code.len() != 2 || !code.chars().all(|c| c.is_ascii_lowercase()) {
+        return None;
+    }
+    code.chars()
+        .map(|c| char::from_u32(0x1F1E6 + (c as u32 - 'a' as u32)))
+        .collect()
+}
+
+/* outbound 文件的增删改 */
+pub struct Outbounds {}
+
+impl Outbounds {
+    /* id 转成 outbound 目录下的路径, 不允许跳出目录 */
+    pub fn resolve(id: &str) -> Result<PathBuf> {
+        let relative = Path::new(id);
+        let is_normal = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if id.is_empty() || !is_normal {
+            anyhow::bail!("invalid outbound id {}", id);
+        }
+        if !ConfigFormat::is_config_file(relative) || OutboundEntry::is_meta_file(relative) {
+            anyhow::bail!("outbound {} must be a json, yaml or toml file", id);
+        }
+        Ok(AppPath::xray_outbound_dir()?.join(relative))
+    }
+
+    pub fn read(id: &str) -> Result<Value> {
+        read_config(&Outbounds::resolve(id)?)
+            .with_context(|| format!("failed to read outbound {}", id))
+    }
+
+    /* 暂存用的内容: 覆盖上元数据里的选项 */
+    pub fn read_staged(id: &str) -> Result<Value> {
+        let mut content = Outbounds::read(id)?;
+        Outbounds::options(id)?.apply(&mut content);
+        Ok(content)
+    }
+
+    pub fn options(id: &str) -> Result<OutboundOptions> {
+        Ok(OutboundEntry::read_meta(&Outbounds::resolve(id)?)?.options)
+    }
+
+    /* 修改选项, 不改 outbound 文件 */
+    pub fn set_options(app: &AppHandle, id: &str, options: OutboundOptions) -> Result<()> {
+        let path = Outbounds::resolve(id)?;
+        if !path.is_file() {
+            anyhow::bail!("outbound {} not found", id);
+        }
+        options.validate(&Variables::substitute(app, &Outbounds::read(id)?)?)?;
+        let mut meta = OutboundEntry::read_meta(&path)?;
+        meta.options = options;
+        OutboundEntry::write_meta(&path, &meta)?;
+
+        if Outbounds::in_use(app, id) {
+            Xray::reload_xray(app)?;
+        }
+        Ok(())
+    }
+
+    /* 当前暂存的配置是否用到这个 outbound */
+    pub fn in_use(app: &AppHandle, id: &str) -> bool {
+        let Some(config) = IConfig::snapshot(app) else {
+            return false;
+        };
+        let active_chain = config
+            .active_chain
+            .as_ref()
+            .and_then(|name| config.chains.iter().find(|chain| &chain.name == name));
+        let active = match active_chain {
+            Some(chain) => chain.dialer == id || chain.outbound == id,
+            None => config.active_outbound == id,
+        };
+        active
+            || config.loaded_outbounds.iter().any(|item| item.id == id)
+            || config
+                .reverse_bridges
+                .iter()
+                .any(|bridge| bridge.outbound == id)
+    }
+
+    pub fn create(app: &AppHandle, id: &str, content: &Value) -> Result<()> {
+        let path = Outbounds::resolve(id)?;
+        if path.exists() {
+            anyhow::bail!("outbound {} already exists", id);
+        }
+        Outbounds::write(app, &path, content)
+    }
+
+    /* 替换内容, 暂存的配置用到时重启 xray */
+    pub fn replace(app: &AppHandle, id: &str, content: &Value) -> Result<()> {
+        let path = Outbounds::resolve(id)?;
+        if !path.is_file() {
+            anyhow::bail!("outbound {} not found", id);
+        }
+        Outbounds::write(app, &path, content)?;
+
+        if Outbounds::in_use(app, id) {
+            Xray::reload_xray(app)?;
+        }
+        Ok(())
+    }
+
+    pub fn rename(app: &AppHandle, id: &str, new_id: &str) -> Result<()> {
+        let (path, new_path) = Outbounds::resolve_pair(id, new_id)?;
+        fs::rename(&path, &new_path)?;
+        Outbounds::move_meta(&path, &new_path, false)?;
+
+        // 当前使用的 outbound, 代理链, 加载的 outbound, 反向代理和方案里的引用一起改
+        IConfig::update(app, |config| {
+            if config.active_outbound == id {
+                config.active_outbound = new_id.to_string();
+            }
+            Chains::rename_outbound(&mut config.chains, id, new_id);
+            Profile::rename_outbound(&mut config.profiles, id, new_id);
+            LoadedOutbounds::rename_outbound(&mut config.loaded_outbounds, id, new_id);
+            ReverseProxies::rename_outbound(&mut config.reverse_bridges, id, new_id);
+        })
+    }
+
+    pub fn duplicate(id: &str, new_id: &str) -> Result<()> {
+        let (path, new_path) = Outbounds::resolve_pair(id, new_id)?;
+        fs::copy(&path, &new_path)?;
+        Outbounds::move_meta(&path, &new_path, true)?;
+        Ok(())
+    }
+
+    /* 删除当前使用的 outbound 时切换到剩下的第一个 */
+    pub fn delete(app: &AppHandle, id: &str) -> Result<()> {
+        let path = Outbounds::resolve(id)?;
+        if !path.is_file() {
+            anyhow::bail!("outbound {} not found", id);
+        }
+
+        let fallback = match IConfig::active_outbound(app).as_deref() == Some(id) {
+            true => Some(
+                IConfig::get_outbound_list()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|outbound| outbound.id != id)
+                    .ok_or(anyhow::anyhow!("can not delete the only outbound {}", id))?,
+            ),
+            false => None,
+        };
+        // 引用它的代理链, 加载项, 反向代理和方案一起删除
+        IConfig::update(app, |config| {
+            if let Some(fallback) = fallback {
+                config.active_outbound = fallback.id;
+            }
+            config
+                .chains
+                .retain(|chain| chain.dialer != id && chain.outbound != id);
+            let active_chain = config.active_chain.as_ref();
+            if !config
+                .chains
+                .iter()
+                .any(|chain| Some(&chain.name) == active_chain)
+            {
+                config.active_chain = None;
+            }
+            Profile::remove_outbound(&mut config.profiles, id, &config.chains);
+            config.loaded_outbounds.retain(|item| item.id != id);
+            config
+                .reverse_bridges
+                .retain(|bridge| bridge.outbound != id);
+        })?;
+
+        fs::remove_file(&path)?;
+        if let Some(meta_path) = OutboundEntry::meta_path(&path) {
+            if meta_path.exists() {
+                fs::remove_file(meta_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_pair(id: &str, new_id: &str) -> Result<(PathBuf, PathBuf)> {
+        let path = Outbounds::resolve(id)?;
+        let new_path = Outbounds::resolve(new_id)?;
+        if !path.is_file() {
+            anyhow::bail!("outbound {} not found", id);
+        }
+        if new_path.exists() {
+            anyhow::bail!("outbound {} already exists", new_id);
+        }
+        // 内容不会转换, 不能改成别的格式
+        if ConfigFormat::of(&path) != ConfigFormat::of(&new_path) {
+            anyhow::bail!("outbound {} and {} are different formats", id, new_id);
+        }
+        if let Some(parent) = new_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok((path, new_path))
+    }
+
+    fn move_meta(path: &Path, new_path: &Path, copy: bool) -> Result<()> {
+        let meta_path = OutboundEntry::meta_path(path);
+        let new_meta_path = OutboundEntry::meta_path(new_path);
+        if let (Some(meta_path), Some(new_meta_path)) = (meta_path, new_meta_path) {
+            if meta_path.exists() {
+                if copy {
+                    fs::copy(meta_path, new_meta_path)?;
+                } else {
+                    fs::rename(meta_path, new_meta_path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* 替换变量后校验, 写入原样的内容 */
+    fn write(app: &AppHandle, path: &Path, content: &Value) -> Result<()> {
+        OutboundConfig::validate(&Variables::substitute(app, content)?)?;
+        ensure_editable(path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json_str = serde_json::to_string_pretty(content)?;
+        Store::write_atomic(path, json_str.as_bytes())
+    }
+}
+
+/* outbound 文件里的出站列表 */
+pub fn outbounds_of(value: &Value, id: &str) -> Result<Vec<Value>> {
+    value
+        .get("outbounds")
+        .and_then(|outbounds| outbounds.as_array())
+        .filter(|outbounds| !outbounds.is_empty())
+        .cloned()
+        .ok_or(anyhow::anyhow!("outbound {} has no outbounds", id))
+}
+
+/* 主出站: tag 为 tag 的, 没有时用第一个 */
+pub fn main_index(outbounds: &[Value], tag: &str) -> usize {
+    outbounds
+        .iter()
+        .position(|outbound| outbound.get("tag").and_then(|t| t.as_str()) == Some(tag))
+        .unwrap_or(0)
+}
+
+/* 出站的 tag, 没有 tag 的用下标 */
+pub fn outbound_tags(outbounds: &[Value]) -> Vec<String> {
+    outbounds
+        .iter()
+        .enumerate()
+        .map(|(index, outbound)| {
+            outbound
+                .get("tag")
+                .and_then(|tag| tag.as_str())
+                .map(|tag| tag.to_string())
+                .unwrap_or(index.to_string())
+        })
+        .collect()
+}
+
+/* 改掉全部出站的 tag, 内部的相互引用一起改 */
+pub fn retag_outbounds<F: Fn(&str) -> String>(outbounds: &mut [Value], retag: F) {
+    let tags = outbound_tags(outbounds);
+    for (outbound, tag) in outbounds.iter_mut().zip(&tags) {
+        outbound["tag"] = Value::from(retag(tag));
+        for pointer in ["/streamSettings/sockopt/dialerProxy", "/proxySettings/tag"] {
+            if let Some(reference) = outbound.pointer_mut(pointer) {
+                if let Some(tag) = reference
+                    .as_str()
+                    .filter(|tag| tags.iter().any(|t| t == tag))
+                {
+                    *reference = Value::from(retag(tag));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_country_from_leading_label_or_tld() {
+        let cases = [
+            ("jp.example.com", Some("jp")),
+            ("us-01.example.com", Some("us")),
+            ("hk2.example.com", Some("hk")),
+            ("SG_3.Example.com.", Some("sg")),
+            ("example.co.jp", Some("jp")),
+            ("node.example.in", Some("in")),
+            ("my.vps.example.com", None),
+            ("in.example.net", None),
+            ("th.example.org", None),
+            ("node-jp.example.com", None),
+            ("us.com", None),
+            ("1.2.3.4", None),
+            ("localhost", None),
+        ];
+        for (address, expected) in cases {
+            assert_eq!(detect_country(address).as_deref(), expected, "{address}");
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::format::{read_config, ConfigFormat};
+use super::path::AppPath;
+
+/* 整个替换预设文件的后缀, 如 00_log.replace.json, 00_log.replace.yaml */
+static REPLACE_SUFFIX: &str = ".replace";
+
+/* 用户的 confdir: 和预设同名的文件深度合并进去, .replace 整个替换, 其它的文件直接加进暂存目录 */
+pub struct ConfdirOverlay {}
+
+impl ConfdirOverlay {
+    /* 复制完预设后覆盖到暂存目录, 任意格式的文件都合并到同名的 .json 上 */
+    pub fn apply(confdir: &Path) -> Result<()> {
+        for file in ConfdirOverlay::user_files()? {
+            let target = confdir.join(format!("{}.json", file.stem));
+            let mut value = match target.is_file() {
+                true => read_config(&target)?,
+                false => Value::Object(Default::default()),
+            };
+            file.apply(&mut value)?;
+            write_json(&target, &value)?;
+            log::debug!(target: "app", "[overlay]: {} overlaid", target.display());
+        }
+        Ok(())
+    }
+
+    /* 预设文件覆盖上用户同名文件后的内容, 和暂存的一致 */
+    pub fn overlaid(stem: &str, preset: Value) -> Result<Value> {
+        let mut value = preset;
+        for file in ConfdirOverlay::user_files()? {
+            if file.stem == stem {
+                file.apply(&mut value)?;
+            }
+        }
+        Ok(value)
+    }
+
+    /* 按文件名排序, 同一个预设的多个文件依次覆盖 */
+    fn user_files() -> Result<Vec<UserFile>> {
+        let user_dir = AppPath::xray_user_config_dir()?;
+        if !user_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut paths: Vec<_> = fs::read_dir(&user_dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && ConfigFormat::is_config_file(path))
+            .collect();
+        paths.sort();
+
+        Ok(paths
+            .into_iter()
+            .filter_map(|path| {
+                let stem = path.file_stem()?.to_str()?.to_string();
+                Some(match stem.strip_suffix(REPLACE_SUFFIX) {
+                    Some(stem) => UserFile {
+                        stem: stem.to_string(),
+                        replace: true,
+                        path,
+                    },
+                    None => UserFile {
+                        stem,
+                        replace: false,
+                        path,
+                    },
+                })
+            })
+            .collect())
+    }
+}
+
+/* 用户 confdir 里的一个文件, stem 是要覆盖的预设文件名 */
+struct UserFile {
+    stem: String,
+    replace: bool,
+    path: PathBuf,
+}
+
+impl UserFile {
+    fn apply(&self, value: &mut Value) -> Result<()> {
+        let overlay = read_config(&self.path)?;
+        match self.replace {
+            true => *value = overlay,
+            false => deep_merge(value, overlay),
+        }
+        Ok(())
+    }
+}
+
+/* 对象逐个键合并; 元素都有 tag 的数组按 tag 合并, 新的 tag 追加; 其它的值直接替换 */
+pub fn deep_merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(exist) => deep_merge(exist, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(overlay))
+            if base
+                .iter()
+                .chain(overlay.iter())
+                .all(|item| tag_of(item).is_some()) =>
+        {
+            for item in overlay {
+                match base.iter_mut().find(|exist| tag_of(exist) == tag_of(&item)) {
+                    Some(exist) => deep_merge(exist, item),
+                    None => base.push(item),
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+fn tag_of(value: &Value) -> Option<&str> {
+    value.get("tag")?.as_str()
+}
+
+fn write_json(path: &Path, value: &Value) -> Result<()> {
+    fs::write(path, serde_json::to_string_pretty(value)?)?;
+    Ok(())
+}
@@ -30,6 +30,11 @@ impl AppPath {
         if !app_log_dir.exists() {
             fs::create_dir_all(&app_log_dir)?;
         }
+
+        let xray_user_config_dir = AppPath::xray_user_config_dir()?;
+        if !xray_user_config_dir.exists() {
+            fs::create_dir_all(&xray_user_config_dir)?;
+        }
         // format!()
         //复制路由
         let xray_routing_dir = AppPath::xray_routing_dir()?;
@@ -98,6 +103,10 @@ impl AppPath {
     pub fn xray_preset_config_dir() -> Result<PathBuf> {
         Ok(AppPath::app_core_dir()?.join("confdir"))
     }
+    /* 用户的配置文件, 暂存时覆盖预设 */
+    pub fn xray_user_config_dir() -> Result<PathBuf> {
+        Ok(AppPath::app_home_dir()?.join("user_confdir"))
+    }
     /* 使用的配置文件 */
     pub fn xray_temp_config_dir() -> Result<PathBuf> {
         Ok(AppPath::app_home_dir()?.join("confdir"))
@@ -111,11 +120,20 @@ impl AppPath {
     pub fn xray_routing_dir() -> Result<PathBuf> {
         Ok(AppPath::app_home_dir()?.join("routing"))
     }
+    /* 路由规则块 */
+    pub fn xray_routing_block_dir() -> Result<PathBuf> {
+        Ok(AppPath::app_home_dir()?.join("routing_block"))
+    }
     /* 代理地址 */
     pub fn xray_outbound_dir() -> Result<PathBuf> {
         Ok(AppPath::app_home_dir()?.join("outbound"))
     }
 
+    /* outbound 对应的服务器配置 */
+    pub fn xray_server_dir() -> Result<PathBuf> {
+        Ok(AppPath::app_home_dir()?.join("server"))
+    }
+
     /* 文件 */
     pub fn xray_pid_path() -> Result<PathBuf> {
         Ok(AppPath::app_home_dir()?.join(XRAY_PID))
@@ -124,4 +142,8 @@ impl AppPath {
     pub fn config_json() -> Result<PathBuf> {
         Ok(AppPath::app_home_dir()?.join(CONFIG_JSON))
     }
+    /* 用户配置备份 */
+    pub fn config_backup_dir() -> Result<PathBuf> {
+        Ok(AppPath::app_home_dir()?.join("backup"))
+    }
 }
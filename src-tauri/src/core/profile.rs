@@ -0,0 +1,162 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+
+use super::chain::{Chains, OutboundChain};
+use super::config::{IConfig, UserConfigValue};
+use super::outbound::Outbounds;
+use super::routing::Routings;
+
+/* 配置方案: 路由/outbound/代理链/系统代理/入站端口 一次切换 */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Profile {
+    pub name: String,
+    pub active_routing: String,
+    pub active_outbound: String,
+    #[serde(default)]
+    pub active_chain: Option<String>,
+    pub sys_port_enable: bool,
+    // 为空时使用预设的端口
+    #[serde(default)]
+    pub http_port: Option<u16>,
+    #[serde(default)]
+    pub socks_port: Option<u16>,
+}
+
+impl Profile {
+    /* 以当前配置生成方案 */
+    pub fn from_config(name: String, config: &UserConfigValue) -> Profile {
+        Profile {
+            name,
+            active_routing: config.active_routing.clone(),
+            active_outbound: config.active_outbound.clone(),
+            active_chain: config.active_chain.clone(),
+            sys_port_enable: config.sys_port_enable,
+            http_port: config.http_port,
+            socks_port: config.socks_port,
+        }
+    }
+
+    /* 当前配置是否就是这个方案 */
+    pub fn matches(&self, config: &UserConfigValue) -> bool {
+        *self == Profile::from_config(self.name.clone(), config)
+    }
+
+    pub fn list(app: &AppHandle) -> Vec<Profile> {
+        IConfig::snapshot(app)
+            .map(|config| config.profiles)
+            .unwrap_or_default()
+    }
+
+    /* 保存当前配置为方案, 同名覆盖 */
+    pub fn save(app: &AppHandle, name: String) -> Result<()> {
+        if name.is_empty() {
+            anyhow::bail!("profile name is empty");
+        }
+        IConfig::update(app, |config| {
+            let profile = Profile::from_config(name, config);
+            match config.profiles.iter_mut().find(|p| p.name == profile.name) {
+                Some(exist) => *exist = profile,
+                None => config.profiles.push(profile),
+            }
+        })
+    }
+
+    pub fn delete(app: &AppHandle, name: &str) -> Result<()> {
+        IConfig::update(app, |config| {
+            config.profiles.retain(|profile| profile.name != name)
+        })
+    }
+
+    /* 应用方案, 一次修改只触发一次重启 */
+    pub fn apply(app: &AppHandle, name: &str) -> Result<()> {
+        let profile = Profile::list(app)
+            .into_iter()
+            .find(|profile| profile.name == name)
+            .ok_or(anyhow::anyhow!("profile {} not found", name))?;
+        // 引用的文件已经不在时不切换, 否则重启会先关掉正在运行的 xray
+        profile.check(app)?;
+
+        IConfig::update(app, |config| {
+            config.active_routing = profile.active_routing;
+            config.active_outbound = profile.active_outbound;
+            config.active_chain = profile.active_chain;
+            config.sys_port_enable = profile.sys_port_enable;
+            config.http_port = profile.http_port;
+            config.socks_port = profile.socks_port;
+        })
+    }
+
+    /* 路由改名时一起修改方案里的引用 */
+    pub fn rename_routing(profiles: &mut [Profile], name: &str, new_name: &str) {
+        for profile in profiles
+            .iter_mut()
+            .filter(|profile| profile.active_routing == name)
+        {
+            profile.active_routing = new_name.to_string();
+        }
+    }
+
+    /* 路由删除时去掉使用它的方案 */
+    pub fn remove_routing(profiles: &mut Vec<Profile>, name: &str) {
+        profiles.retain(|profile| profile.active_routing != name);
+    }
+
+    /* outbound 改名时一起修改方案里的引用 */
+    pub fn rename_outbound(profiles: &mut [Profile], id: &str, new_id: &str) {
+        for profile in profiles
+            .iter_mut()
+            .filter(|profile| profile.active_outbound == id)
+        {
+            profile.active_outbound = new_id.to_string();
+        }
+    }
+
+    /* outbound 删除时去掉使用它的方案, 已经删除的代理链不再选择 */
+    pub fn remove_outbound(profiles: &mut Vec<Profile>, id: &str, chains: &[OutboundChain]) {
+        profiles.retain(|profile| profile.active_outbound != id);
+        for profile in profiles.iter_mut() {
+            if profile
+                .active_chain
+                .as_ref()
+                .is_some_and(|name| !chains.iter().any(|chain| chain.name == *name))
+            {
+                profile.active_chain = None;
+            }
+        }
+    }
+
+    /* 方案引用的路由/outbound/代理链都还存在 */
+    fn check(&self, app: &AppHandle) -> Result<()> {
+        if !Routings::resolve(&self.active_routing)?.is_file() {
+            anyhow::bail!(
+                "routing {} of profile {} not found",
+                self.active_routing,
+                self.name
+            );
+        }
+        if !Outbounds::resolve(&self.active_outbound)?.is_file() {
+            anyhow::bail!(
+                "outbound {} of profile {} not found",
+                self.active_outbound,
+                self.name
+            );
+        }
+        if let Some(chain_name) = &self.active_chain {
+            let chain = Chains::list(app)
+                .into_iter()
+                .find(|chain| chain.name == *chain_name)
+                .ok_or(anyhow::anyhow!(
+                    "chain {} of profile {} not found",
+                    chain_name,
+                    self.name
+                ))?;
+            for id in [&chain.dialer, &chain.outbound] {
+                if !Outbounds::resolve(id)?.is_file() {
+                    anyhow::bail!("outbound {} of chain {} not found", id, chain.name);
+                }
+            }
+        }
+        Ok(())
+    }
+}
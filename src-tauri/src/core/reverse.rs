@@ -0,0 +1,417 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use tauri::AppHandle;
+
+use super::config::IConfig;
+use super::outbound::{main_index, outbound_tags, outbounds_of, retag_outbounds, Outbounds};
+
+/* 反向代理: 本机是 bridge, 通过 outbound 连到服务器上的 portal, 服务器的端口转发到本机的服务 */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ReverseBridge {
+    // 生成的 tag 的前缀
+    pub name: String,
+    // bridge 和 portal 约定的域名, 只用于识别反向连接, 不需要解析
+    pub domain: String,
+    // 连到服务器的 outbound id
+    pub outbound: String,
+    // 要暴露的本机服务, 如 127.0.0.1:80
+    pub local_address: String,
+    // 服务器上 bridge 连入的入站 tag
+    pub portal_inbound_tag: String,
+    // 服务器对外开放的端口
+    pub portal_port: u16,
+}
+
+/* 暂存的反向代理配置, 出站放在最后 */
+static STAGED_REVERSE: &str = "96.reverse.tail.json";
+
+/* 两边的配置, 服务器那边的需要手动合并 */
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReverseConfig {
+    pub bridge: Value,
+    pub portal: Value,
+    // tag 对不上的地方
+    pub issues: Vec<String>,
+}
+
+pub struct ReverseProxies {}
+
+impl ReverseProxies {
+    pub fn list(app: &AppHandle) -> Vec<ReverseBridge> {
+        IConfig::snapshot(app)
+            .map(|config| config.reverse_bridges)
+            .unwrap_or_default()
+    }
+
+    /* 保存反向代理, 同名覆盖 */
+    pub fn save(app: &AppHandle, bridge: ReverseBridge) -> Result<()> {
+        let valid_name = !bridge.name.is_empty()
+            && bridge
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            anyhow::bail!("invalid reverse name {}", bridge.name);
+        }
+        if !bridge.domain.contains('.') || bridge.domain.contains([':', '/']) {
+            anyhow::bail!("invalid reverse domain {}", bridge.domain);
+        }
+        if bridge.local_address.parse::<SocketAddr>().is_err() {
+            anyhow::bail!("invalid local address {}", bridge.local_address);
+        }
+        if bridge.portal_inbound_tag.is_empty() {
+            anyhow::bail!("portal inbound tag is empty");
+        }
+        if bridge.portal_port == 0 {
+            anyhow::bail!("invalid portal port");
+        }
+        if !Outbounds::resolve(&bridge.outbound)?.is_file() {
+            anyhow::bail!("outbound {} not found", bridge.outbound);
+        }
+        if ReverseProxies::list(app)
+            .iter()
+            .any(|exist| exist.name != bridge.name && exist.domain == bridge.domain)
+        {
+            anyhow::bail!("reverse domain {} is already used", bridge.domain);
+        }
+
+        IConfig::update(app, |config| {
+            match config
+                .reverse_bridges
+                .iter_mut()
+                .find(|exist| exist.name == bridge.name)
+            {
+                Some(exist) => *exist = bridge,
+                None => config.reverse_bridges.push(bridge),
+            }
+        })
+    }
+
+    pub fn delete(app: &AppHandle, name: &str) -> Result<()> {
+        IConfig::update(app, |config| {
+            config.reverse_bridges.retain(|bridge| bridge.name != name)
+        })
+    }
+
+    /* outbound 改名时同步 */
+    pub fn rename_outbound(bridges: &mut [ReverseBridge], id: &str, new_id: &str) {
+        for bridge in bridges.iter_mut().filter(|bridge| bridge.outbound == id) {
+            bridge.outbound = new_id.to_string();
+        }
+    }
+
+    /* 生成两边的配置并检查 tag */
+    pub fn generate(app: &AppHandle, name: &str) -> Result<ReverseConfig> {
+        let bridge = ReverseProxies::list(app)
+            .into_iter()
+            .find(|bridge| bridge.name == name)
+            .ok_or(anyhow::anyhow!("reverse {} not found", name))?;
+
+        let bridge_config = ReverseProxies::render(std::slice::from_ref(&bridge))?;
+        let portal_config = ReverseProxies::portal(&bridge);
+        let issues = ReverseProxies::check(&bridge_config, &portal_config);
+        Ok(ReverseConfig {
+            bridge: bridge_config,
+            portal: portal_config,
+            issues,
+        })
+    }
+
+    /* 本机的配置: reverse.bridges, 连服务器的 outbound, 转发到本机服务的 outbound 和路由规则 */
+    pub fn render(bridges: &[ReverseBridge]) -> Result<Value> {
+        let mut reverse_bridges = Vec::new();
+        let mut outbounds = Vec::new();
+        let mut rules = Vec::new();
+        for bridge in bridges {
+            let tags = BridgeTags::new(&bridge.name);
+
+            // 连服务器的 outbound, 文件里其它出站的 tag 加上前缀
+            let mut tunnel =
+                outbounds_of(&Outbounds::read_staged(&bridge.outbound)?, &bridge.outbound)?;
+            let main_tag = outbound_tags(&tunnel)[main_index(&tunnel, "proxy")].clone();
+            retag_outbounds(&mut tunnel, |tag| match tag == main_tag {
+                true => tags.tunnel.clone(),
+                false => format!("{}-{}", tags.tunnel, tag),
+            });
+            outbounds.extend(tunnel);
+            outbounds.push(json!({
+                "tag": tags.local,
+                "protocol": "freedom",
+                "settings": { "redirect": bridge.local_address }
+            }));
+
+            reverse_bridges.push(json!({ "tag": tags.bridge, "domain": bridge.domain }));
+            // 反向连接走 tunnel, 服务器转发过来的流量给本机服务
+            rules.push(json!({
+                "type": "field",
+                "inboundTag": [tags.bridge],
+                "domain": [format!("full:{}", bridge.domain)],
+                "outboundTag": tags.tunnel
+            }));
+            rules.push(json!({
+                "type": "field",
+                "inboundTag": [tags.bridge],
+                "outboundTag": tags.local
+            }));
+        }
+
+        Ok(json!({
+            "reverse": { "bridges": reverse_bridges },
+            "outbounds": outbounds,
+            "routing": { "rules": rules }
+        }))
+    }
+
+    /* 暂存反向代理, 路由规则插到暂存路由的最前面, 不会被其它规则先匹配 */
+    pub fn stage(app: &AppHandle, confdir: &Path, routing: &mut Value) -> Result<()> {
+        let bridges = ReverseProxies::list(app);
+        if bridges.is_empty() {
+            return Ok(());
+        }
+
+        let mut reverse = ReverseProxies::render(&bridges)?;
+        let rules = reverse
+            .get_mut("routing")
+            .and_then(|routing| routing.get_mut("rules"))
+            .map(Value::take)
+            .and_then(|rules| match rules {
+                Value::Array(rules) => Some(rules),
+                _ => None,
+            })
+            .unwrap_or_default();
+        if let Some(reverse) = reverse.as_object_mut() {
+            reverse.remove("routing");
+        }
+        let Some(routing) = routing
+            .get_mut("routing")
+            .and_then(|routing| routing.as_object_mut())
+        else {
+            anyhow::bail!("invalid staged routing");
+        };
+        if let Some(staged_rules) = routing
+            .entry("rules")
+            .or_insert(Value::Array(Vec::new()))
+            .as_array_mut()
+        {
+            staged_rules.splice(0..0, rules);
+        }
+
+        fs::write(
+            confdir.join(STAGED_REVERSE),
+            serde_json::to_string_pretty(&reverse)?,
+        )?;
+        Ok(())
+    }
+
+    /* 服务器的配置: reverse.portals, 对外开放的入站和路由规则 */
+    pub fn portal(bridge: &ReverseBridge) -> Value {
+        let tags = BridgeTags::new(&bridge.name);
+        let local_port = bridge
+            .local_address
+            .parse::<SocketAddr>()
+            .map(|address| address.port())
+            .unwrap_or(bridge.portal_port);
+        json!({
+            "reverse": {
+                "portals": [{ "tag": tags.portal, "domain": bridge.domain }]
+            },
+            "inbounds": [{
+                "tag": tags.external,
+                "port": bridge.portal_port,
+                "protocol": "dokodemo-door",
+                "settings": {
+                    "address": "127.0.0.1",
+                    "port": local_port,
+                    "network": "tcp"
+                }
+            }],
+            "routing": {
+                "rules": [
+                    {
+                        "type": "field",
+                        "inboundTag": [tags.external],
+                        "outboundTag": tags.portal
+                    },
+                    {
+                        "type": "field",
+                        "inboundTag": [bridge.portal_inbound_tag],
+                        "domain": [format!("full:{}", bridge.domain)],
+                        "outboundTag": tags.portal
+                    }
+                ]
+            }
+        })
+    }
+
+    /* 检查两边的 tag: 域名一致, bridge 的反向连接有出口, portal 有对外入站和反向连接入站 */
+    pub fn check(bridge_config: &Value, portal_config: &Value) -> Vec<String> {
+        let mut issues = Vec::new();
+        let bridge_outbounds = tags_of(bridge_config.get("outbounds"));
+        let bridge_rules = rules_of(bridge_config);
+        let portal_inbounds = tags_of(portal_config.get("inbounds"));
+        let portal_rules = rules_of(portal_config);
+        let portals = reverse_of(portal_config, "portals");
+
+        for (tag, domain) in reverse_of(bridge_config, "bridges") {
+            let Some((portal_tag, _)) = portals.iter().find(|(_, d)| *d == domain) else {
+                issues.push(format!("bridge {}: no portal uses domain {}", tag, domain));
+                continue;
+            };
+
+            let bridge_rule = |with_domain: bool| {
+                bridge_rules.iter().find(|rule| {
+                    rule.inbound_tags.contains(&tag) && rule.has_domain(&domain) == with_domain
+                })
+            };
+            match bridge_rule(true) {
+                Some(rule) if bridge_outbounds.contains(&rule.outbound_tag) => {}
+                Some(rule) => issues.push(format!(
+                    "bridge {}: tunnel outbound {} is not defined",
+                    tag, rule.outbound_tag
+                )),
+                None => issues.push(format!(
+                    "bridge {}: no rule routes {} to the portal",
+                    tag, domain
+                )),
+            }
+            match bridge_rule(false) {
+                Some(rule) if bridge_outbounds.contains(&rule.outbound_tag) => {}
+                Some(rule) => issues.push(format!(
+                    "bridge {}: local outbound {} is not defined",
+                    tag, rule.outbound_tag
+                )),
+                None => issues.push(format!(
+                    "bridge {}: no rule routes traffic to a local service",
+                    tag
+                )),
+            }
+
+            // portal 的反向连接入站在服务器已有的配置里, 只检查规则
+            let to_portal: Vec<&Rule> = portal_rules
+                .iter()
+                .filter(|rule| &rule.outbound_tag == portal_tag)
+                .collect();
+            if !to_portal.iter().any(|rule| rule.has_domain(&domain)) {
+                issues.push(format!(
+                    "portal {}: no rule accepts the bridge connection",
+                    portal_tag
+                ));
+            }
+            let external = to_portal
+                .iter()
+                .filter(|rule| !rule.has_domain(&domain))
+                .flat_map(|rule| rule.inbound_tags.iter())
+                .collect::<Vec<_>>();
+            if external.is_empty() {
+                issues.push(format!("portal {}: no inbound is routed to it", portal_tag));
+            }
+            for inbound in external
+                .into_iter()
+                .filter(|tag| !portal_inbounds.contains(*tag))
+            {
+                issues.push(format!(
+                    "portal {}: inbound {} is not defined",
+                    portal_tag, inbound
+                ));
+            }
+        }
+        issues
+    }
+}
+
+/* 由名字生成的 tag, 两边一致 */
+struct BridgeTags {
+    bridge: String,
+    tunnel: String,
+    local: String,
+    portal: String,
+    external: String,
+}
+
+impl BridgeTags {
+    fn new(name: &str) -> BridgeTags {
+        BridgeTags {
+            bridge: format!("{}-bridge", name),
+            tunnel: format!("{}-tunnel", name),
+            local: format!("{}-local", name),
+            portal: format!("{}-portal", name),
+            external: format!("{}-external", name),
+        }
+    }
+}
+
+/* 检查用到的规则字段 */
+struct Rule {
+    inbound_tags: Vec<String>,
+    domains: Vec<String>,
+    outbound_tag: String,
+}
+
+impl Rule {
+    fn has_domain(&self, domain: &str) -> bool {
+        self.domains
+            .iter()
+            .any(|d| d == domain || d.strip_prefix("full:") == Some(domain))
+    }
+}
+
+fn rules_of(config: &Value) -> Vec<Rule> {
+    let strings = |value: Option<&Value>| -> Vec<String> {
+        match value {
+            Some(Value::String(s)) => vec![s.clone()],
+            Some(Value::Array(list)) => list
+                .iter()
+                .filter_map(|item| item.as_str().map(|s| s.to_string()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    };
+    config
+        .pointer("/routing/rules")
+        .and_then(|rules| rules.as_array())
+        .map(|rules| {
+            rules
+                .iter()
+                .map(|rule| Rule {
+                    inbound_tags: strings(rule.get("inboundTag")),
+                    domains: strings(rule.get("domain")),
+                    outbound_tag: strings(rule.get("outboundTag")).concat(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn tags_of(list: Option<&Value>) -> HashSet<String> {
+    list.and_then(|list| list.as_array())
+        .map(|list| {
+            list.iter()
+                .filter_map(|item| item.get("tag")?.as_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/* reverse.bridges/portals 的 (tag, domain) */
+fn reverse_of(config: &Value, key: &str) -> Vec<(String, String)> {
+    config
+        .get("reverse")
+        .and_then(|reverse| reverse.get(key))
+        .and_then(|list| list.as_array())
+        .map(|list| {
+            list.iter()
+                .filter_map(|item| {
+                    let tag = item.get("tag")?.as_str()?;
+                    let domain = item.get("domain")?.as_str()?;
+                    Some((tag.to_string(), domain.to_string()))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
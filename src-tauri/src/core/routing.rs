@@ -0,0 +1,303 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Component, Path, PathBuf};
+use tauri::AppHandle;
+
+use super::config::IConfig;
+use super::format::{ensure_editable, read_config, ConfigFormat};
+use super::path::AppPath;
+use super::profile::Profile;
+use super::schema::{RoutingConfig, RuleObject};
+use super::store::Store;
+use super::variable::Variables;
+use super::xray::Xray;
+
+/* 托盘快捷规则的 ruleTag 前缀 */
+pub static QUICK_RULE_PREFIX: &str = "quick-";
+
+/* 路由文件和规则的增删改 */
+pub struct Routings {}
+
+impl Routings {
+    /* 文件名转成路由目录下的路径, 不允许跳出目录 */
+    pub fn resolve(name: &str) -> Result<PathBuf> {
+        let relative = Path::new(name);
+        let mut components = relative.components();
+        let is_file_name =
+            matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+        if !is_file_name || !ConfigFormat::is_config_file(relative) {
+            anyhow::bail!("invalid routing file {}", name);
+        }
+        Ok(AppPath::xray_routing_dir()?.join(relative))
+    }
+
+    /* 原样读出, 变量不替换, 修改后写回不会丢掉变量 */
+    pub fn read(app: &AppHandle, name: &str) -> Result<RoutingConfig> {
+        let value = Routings::read_value(name)?;
+        RoutingConfig::validate(&Variables::substitute(app, &value)?)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /* 替换变量后的内容, 和暂存的一致 */
+    pub fn read_resolved(app: &AppHandle, name: &str) -> Result<RoutingConfig> {
+        RoutingConfig::validate(&Variables::substitute(app, &Routings::read_value(name)?)?)
+    }
+
+    fn read_value(name: &str) -> Result<Value> {
+        read_config(&Routings::resolve(name)?)
+            .with_context(|| format!("failed to read routing {}", name))
+    }
+
+    /* 替换变量后校验, 写入原样的内容, 当前使用的路由会重启 xray */
+    pub fn write(app: &AppHandle, name: &str, config: &RoutingConfig) -> Result<()> {
+        let path = Routings::resolve(name)?;
+        let value = serde_json::to_value(config)?;
+        RoutingConfig::validate(&Variables::substitute(app, &value)?)?;
+        ensure_editable(&path)?;
+        let json_str = serde_json::to_string_pretty(&value)?;
+        Store::write_atomic(&path, json_str.as_bytes())?;
+
+        if IConfig::active_routing(app).as_deref() == Some(name) {
+            Xray::reload_xray(app)?;
+        }
+        Ok(())
+    }
+
+    pub fn create(app: &AppHandle, name: &str, content: Option<Value>) -> Result<()> {
+        let path = Routings::resolve(name)?;
+        if path.exists() {
+            anyhow::bail!("routing {} already exists", name);
+        }
+        let content = content.unwrap_or_else(|| {
+            json!({
+                "routing": {
+                    "domainStrategy": "IPIfNonMatch",
+                    "rules": []
+                }
+            })
+        });
+        Routings::write(app, name, &RoutingConfig::validate(&content)?)
+    }
+
+    pub fn rename(app: &AppHandle, name: &str, new_name: &str) -> Result<()> {
+        let path = Routings::resolve(name)?;
+        let new_path = Routings::resolve(new_name)?;
+        if !path.is_file() {
+            anyhow::bail!("routing {} not found", name);
+        }
+        if new_path.exists() {
+            anyhow::bail!("routing {} already exists", new_name);
+        }
+        // 内容不会转换, 不能改成别的格式
+        if ConfigFormat::of(&path) != ConfigFormat::of(&new_path) {
+            anyhow::bail!("routing {} and {} are different formats", name, new_name);
+        }
+        fs::rename(path, new_path)?;
+
+        // 当前使用的路由和方案里的引用一起改
+        IConfig::update(app, |config| {
+            if config.active_routing == name {
+                config.active_routing = new_name.to_string();
+            }
+            Profile::rename_routing(&mut config.profiles, name, new_name);
+        })
+    }
+
+    /* 删除当前使用的路由时切换到剩下的第一个, 使用它的方案一起删除 */
+    pub fn delete(app: &AppHandle, name: &str) -> Result<()> {
+        let path = Routings::resolve(name)?;
+        if !path.is_file() {
+            anyhow::bail!("routing {} not found", name);
+        }
+
+        let fallback = match IConfig::active_routing(app).as_deref() == Some(name) {
+            true => Some(
+                IConfig::get_routing_list()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|path| path.file_name()?.to_str().map(|s| s.to_string()))
+                    .find(|file_name| file_name != name)
+                    .ok_or(anyhow::anyhow!("can not delete the only routing {}", name))?,
+            ),
+            false => None,
+        };
+        IConfig::update(app, |config| {
+            if let Some(fallback) = fallback {
+                config.active_routing = fallback;
+            }
+            Profile::remove_routing(&mut config.profiles, name);
+        })?;
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /* 规则 */
+    pub fn list_rules(app: &AppHandle, name: &str) -> Result<Vec<RuleObject>> {
+        Ok(Routings::read(app, name)?.routing.rules)
+    }
+
+    /* 插入规则, index 为空时加到最后 */
+    pub fn insert_rule(
+        app: &AppHandle,
+        name: &str,
+        index: Option<usize>,
+        rule: RuleObject,
+    ) -> Result<()> {
+        rule.validate()?;
+        Routings::edit_rules(app, name, |rules| {
+            let index = index.unwrap_or(rules.len()).min(rules.len());
+            rules.insert(index, rule);
+            Ok(())
+        })
+    }
+
+    pub fn update_rule(app: &AppHandle, name: &str, index: usize, rule: RuleObject) -> Result<()> {
+        rule.validate()?;
+        Routings::edit_rules(app, name, |rules| {
+            *Routings::rule_mut(rules, index)? = rule;
+            Ok(())
+        })
+    }
+
+    pub fn move_rule(app: &AppHandle, name: &str, from: usize, to: usize) -> Result<()> {
+        Routings::edit_rules(app, name, |rules| {
+            Routings::rule_mut(rules, from)?;
+            let rule = rules.remove(from);
+            rules.insert(to.min(rules.len()), rule);
+            Ok(())
+        })
+    }
+
+    pub fn set_rule_enabled(
+        app: &AppHandle,
+        name: &str,
+        index: usize,
+        enabled: bool,
+    ) -> Result<()> {
+        Routings::edit_rules(app, name, |rules| {
+            Routings::rule_mut(rules, index)?.enabled = enabled;
+            Ok(())
+        })
+    }
+
+    pub fn delete_rule(app: &AppHandle, name: &str, index: usize) -> Result<()> {
+        Routings::edit_rules(app, name, |rules| {
+            Routings::rule_mut(rules, index)?;
+            rules.remove(index);
+            Ok(())
+        })
+    }
+
+    fn edit_rules<F>(app: &AppHandle, name: &str, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Vec<RuleObject>) -> Result<()>,
+    {
+        let mut config = Routings::read(app, name)?;
+        f(&mut config.routing.rules)?;
+        Routings::write(app, name, &config)
+    }
+
+    fn rule_mut(rules: &mut [RuleObject], index: usize) -> Result<&mut RuleObject> {
+        let len = rules.len();
+        rules
+            .get_mut(index)
+            .ok_or(anyhow::anyhow!("rule index {} out of range {}", index, len))
+    }
+
+    /* 把域名加到当前路由最前面的快捷规则里, 并从其它快捷规则里去掉 */
+    pub fn add_quick_domain(app: &AppHandle, input: &str, outbound_tag: &str) -> Result<String> {
+        let domain = registrable_domain(input)
+            .ok_or(anyhow::anyhow!("no domain found in {}", input.trim()))?;
+        let name = IConfig::active_routing(app)
+            .filter(|name| !name.is_empty())
+            .ok_or(anyhow::anyhow!("no active routing"))?;
+
+        let matcher = format!("domain:{}", domain);
+        let rule_tag = format!("{}{}", QUICK_RULE_PREFIX, outbound_tag);
+        let is_quick = |rule: &RuleObject| {
+            rule.rule_tag
+                .as_deref()
+                .is_some_and(|tag| tag.starts_with(QUICK_RULE_PREFIX))
+        };
+
+        Routings::edit_rules(app, &name, |rules| {
+            for rule in rules.iter_mut().filter(|rule| is_quick(rule)) {
+                if let Some(domains) = rule.domain.as_mut() {
+                    domains.retain(|domain| *domain != matcher);
+                }
+            }
+            // 去掉已经清空的快捷规则
+            rules.retain(|rule| {
+                !is_quick(rule) || rule.domain.as_ref().is_some_and(|list| !list.is_empty())
+            });
+
+            match rules
+                .iter_mut()
+                .find(|rule| rule.rule_tag.as_deref() == Some(rule_tag.as_str()))
+            {
+                Some(rule) => rule.domain.get_or_insert_with(Vec::new).insert(0, matcher),
+                None => rules.insert(
+                    0,
+                    RuleObject {
+                        domain: Some(vec![matcher]),
+                        outbound_tag: Some(outbound_tag.to_string()),
+                        rule_tag: Some(rule_tag),
+                        ..RuleObject::default()
+                    },
+                ),
+            }
+            Ok(())
+        })?;
+
+        Ok(domain)
+    }
+
+    /* 暂存给 xray 的路由: 去掉禁用的规则和 enabled 字段 */
+    pub fn render(path: &Path) -> Result<Value> {
+        let mut value = read_config(path)?;
+
+        if let Some(rules) = value
+            .get_mut("routing")
+            .and_then(|routing| routing.get_mut("rules"))
+            .and_then(|rules| rules.as_array_mut())
+        {
+            rules.retain(|rule| rule.get("enabled") != Some(&Value::Bool(false)));
+            for rule in rules.iter_mut() {
+                if let Some(rule) = rule.as_object_mut() {
+                    rule.remove("enabled");
+                }
+            }
+        }
+
+        Ok(value)
+    }
+}
+
+/* 从 url 或域名里取出可注册域名, 按公共后缀列表, 如 https://www.bbc.co.uk/news -> bbc.co.uk */
+pub fn registrable_domain(input: &str) -> Option<String> {
+    let input = input.trim();
+    let without_scheme = input
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(input);
+    let authority = without_scheme.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?.split(':').next()?;
+    let host = host.trim_end_matches('.').to_lowercase();
+
+    if host.parse::<IpAddr>().is_ok()
+        || !host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    {
+        return None;
+    }
+
+    if host.split('.').any(|label| label.is_empty()) {
+        return None;
+    }
+    // 本身就是公共后缀时没有可注册域名
+    psl::domain_str(&host).map(|domain| domain.to_string())
+}
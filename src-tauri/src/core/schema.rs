@@ -0,0 +1,405 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+/* xray 配置结构, see https://xtls.github.io/config/ */
+
+/* outbound 文件 */
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboundConfig {
+    pub outbounds: Vec<OutboundObject>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboundObject {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub send_through: Option<String>,
+    pub protocol: OutboundProtocol,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_settings: Option<StreamSettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_settings: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mux: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboundProtocol {
+    Blackhole,
+    Dns,
+    Freedom,
+    Http,
+    Loopback,
+    Shadowsocks,
+    Socks,
+    Trojan,
+    Vless,
+    Vmess,
+    Wireguard,
+}
+
+/* vless/vmess 的 settings */
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VnextSettings {
+    pub vnext: Vec<VnextServer>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VnextServer {
+    pub address: String,
+    pub port: u16,
+    pub users: Vec<VnextUser>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VnextUser {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/* trojan/shadowsocks/socks/http 的 settings */
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ServersSettings {
+    pub servers: Vec<ServerObject>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ServerObject {
+    pub address: String,
+    pub port: u16,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<Network>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security: Option<Security>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_settings: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reality_settings: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sockopt: Option<Value>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Tcp,
+    Raw,
+    Kcp,
+    Ws,
+    Http,
+    H2,
+    Quic,
+    Grpc,
+    Httpupgrade,
+    Splithttp,
+    Xhttp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    None,
+    Tls,
+    Reality,
+}
+
+impl OutboundConfig {
+    /* 按 xray 的结构校验 outbound 文件 */
+    pub fn validate(value: &Value) -> Result<OutboundConfig> {
+        let config: OutboundConfig = serde_json::from_value(value.clone())?;
+        if config.outbounds.is_empty() {
+            anyhow::bail!("outbounds is empty");
+        }
+
+        let mut tags = HashSet::new();
+        for (index, outbound) in config.outbounds.iter().enumerate() {
+            outbound
+                .validate()
+                .map_err(|err| anyhow::anyhow!("outbounds[{}]: {}", index, err))?;
+            if let Some(tag) = &outbound.tag {
+                if !tags.insert(tag.as_str()) {
+                    anyhow::bail!("outbounds[{}]: duplicate tag {}", index, tag);
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl OutboundObject {
+    pub fn validate(&self) -> Result<()> {
+        let settings = self.settings.clone().unwrap_or(Value::Null);
+        match self.protocol {
+            OutboundProtocol::Vless | OutboundProtocol::Vmess => {
+                let settings: VnextSettings = serde_json::from_value(settings)?;
+                if settings.vnext.is_empty() {
+                    anyhow::bail!("settings.vnext is empty");
+                }
+                if settings.vnext.iter().any(|server| server.users.is_empty()) {
+                    anyhow::bail!("settings.vnext.users is empty");
+                }
+            }
+            OutboundProtocol::Trojan
+            | OutboundProtocol::Shadowsocks
+            | OutboundProtocol::Socks
+            | OutboundProtocol::Http => {
+                let settings: ServersSettings = serde_json::from_value(settings)?;
+                if settings.servers.is_empty() {
+                    anyhow::bail!("settings.servers is empty");
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(stream_settings) = &self.stream_settings {
+            match stream_settings.security {
+                Some(Security::Tls) if stream_settings.reality_settings.is_some() => {
+                    anyhow::bail!("realitySettings requires security reality")
+                }
+                Some(Security::Reality) if stream_settings.reality_settings.is_none() => {
+                    anyhow::bail!("security reality requires realitySettings")
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/* 路由文件 */
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingConfig {
+    pub routing: RoutingObject,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingObject {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain_strategy: Option<DomainStrategy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain_matcher: Option<String>,
+    #[serde(default)]
+    pub rules: Vec<RuleObject>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balancers: Option<Vec<Value>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DomainStrategy {
+    AsIs,
+    IPIfNonMatch,
+    IPOnDemand,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleObject {
+    #[serde(rename = "type", default = "default_rule_type")]
+    pub rule_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<PortList>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_port: Option<PortList>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inbound_tag: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outbound_tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balancer_tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule_tag: Option<String>,
+    // 不是 xray 的字段, 禁用的规则在暂存配置时去掉
+    #[serde(default = "default_enabled", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/* 端口, 可以是数字或 "53,443,1000-2000" */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum PortList {
+    Number(u16),
+    Text(String),
+}
+
+fn default_rule_type() -> String {
+    "field".to_string()
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
+impl RoutingConfig {
+    /* 按 xray 的结构校验路由文件 */
+    pub fn validate(value: &Value) -> Result<RoutingConfig> {
+        let config: RoutingConfig = serde_json::from_value(value.clone())?;
+        for (index, rule) in config.routing.rules.iter().enumerate() {
+            rule.validate()
+                .map_err(|err| anyhow::anyhow!("rules[{}]: {}", index, err))?;
+        }
+        Ok(config)
+    }
+}
+
+impl Default for RuleObject {
+    fn default() -> Self {
+        RuleObject {
+            rule_type: default_rule_type(),
+            domain: None,
+            ip: None,
+            port: None,
+            source_port: None,
+            network: None,
+            source: None,
+            user: None,
+            inbound_tag: None,
+            protocol: None,
+            outbound_tag: None,
+            balancer_tag: None,
+            rule_tag: None,
+            enabled: true,
+            extra: Map::new(),
+        }
+    }
+}
+
+impl RuleObject {
+    /* 匹配所有流量的规则, 后面的规则不会生效; 只按入站匹配的也算, 预设路由的最后一条就是这样 */
+    pub fn is_catch_all(&self) -> bool {
+        let has_matcher = [
+            &self.domain,
+            &self.ip,
+            &self.source,
+            &self.user,
+            &self.protocol,
+        ]
+        .iter()
+        .any(|matcher| matcher.as_ref().is_some_and(|list| !list.is_empty()))
+            || self.port.is_some()
+            || self.source_port.is_some()
+            || self.extra.contains_key("attrs");
+        let all_network = match self.network.as_deref() {
+            Some(network) => {
+                let networks: Vec<&str> = network.split(',').map(|item| item.trim()).collect();
+                networks.contains(&"tcp") && networks.contains(&"udp")
+            }
+            None => true,
+        };
+        self.enabled && !has_matcher && all_network
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.rule_type != "field" {
+            anyhow::bail!("unsupported rule type {}", self.rule_type);
+        }
+
+        let has_matcher = [
+            &self.domain,
+            &self.ip,
+            &self.source,
+            &self.user,
+            &self.inbound_tag,
+            &self.protocol,
+        ]
+        .iter()
+        .any(|matcher| matcher.as_ref().is_some_and(|list| !list.is_empty()))
+            || self.port.is_some()
+            || self.source_port.is_some()
+            || self.network.is_some()
+            || self.extra.contains_key("attrs");
+        if !has_matcher {
+            anyhow::bail!("rule has no matcher");
+        }
+
+        match (&self.outbound_tag, &self.balancer_tag) {
+            (Some(_), Some(_)) => anyhow::bail!("only one of outboundTag and balancerTag"),
+            (None, None) => anyhow::bail!("outboundTag or balancerTag is required"),
+            _ => {}
+        }
+
+        if let Some(network) = &self.network {
+            let valid = network
+                .split(',')
+                .map(|network| network.trim())
+                .all(|network| network == "tcp" || network == "udp");
+            if !valid {
+                anyhow::bail!("invalid network {}", network);
+            }
+        }
+
+        for port in [&self.port, &self.source_port].into_iter().flatten() {
+            port.ranges()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PortList {
+    /* 展开成端口区间 */
+    pub fn ranges(&self) -> Result<Vec<(u16, u16)>> {
+        match self {
+            PortList::Number(port) => Ok(vec![(*port, *port)]),
+            PortList::Text(text) => text
+                .split(',')
+                .map(|part| part.trim())
+                .filter(|part| !part.is_empty())
+                .map(|part| {
+                    let (from, to) = part.split_once('-').unwrap_or((part, part));
+                    let from: u16 = from.trim().parse()?;
+                    let to: u16 = to.trim().parse()?;
+                    if from > to {
+                        anyhow::bail!("invalid port range {}", part);
+                    }
+                    Ok((from, to))
+                })
+                .collect(),
+        }
+    }
+}
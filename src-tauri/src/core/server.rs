@@ -0,0 +1,349 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::PathBuf;
+use tauri::AppHandle;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use super::outbound::{main_index, outbounds_of, Outbounds};
+use super::path::AppPath;
+use super::schema::Security;
+use super::store::Store;
+use super::variable::Variables;
+
+/* 服务器上的证书路径, 生成后按实际位置修改 */
+static CERTIFICATE_FILE: &str = "/etc/xray/fullchain.pem";
+static KEY_FILE: &str = "/etc/xray/privkey.pem";
+
+/* 找不到和客户端公钥对应的私钥时, 服务器配置里先用这个占位, 由用户填上服务器现有的私钥 */
+static PRIVATE_KEY_PLACEHOLDER: &str = "REPLACE_WITH_SERVER_PRIVATE_KEY";
+
+/* 新建 vless 节点 */
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewNode {
+    // 写入的 outbound id
+    pub id: String,
+    // 服务器地址
+    pub address: String,
+    pub port: u16,
+    // tls 或 reality
+    pub security: Security,
+    // tls 时是证书的域名, reality 时是伪装的网站
+    pub server_name: String,
+}
+
+/* 客户端 outbound 和服务器配置 */
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerPair {
+    pub id: String,
+    pub outbound: Value,
+    pub server: Value,
+    // reality 的私钥是占位的, 需要填上服务器现有的私钥
+    pub missing_private_key: bool,
+}
+
+/* reality 的 x25519 密钥对, base64 url 编码, 和 xray x25519 的输出一致 */
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct X25519Pair {
+    pub private_key: String,
+    pub public_key: String,
+}
+
+pub struct Servers {}
+
+impl Servers {
+    /* 服务器配置和 outbound 用相同的相对路径, 写出的都是 json, 扩展名统一成 .json */
+    pub fn resolve(id: &str) -> Result<PathBuf> {
+        Outbounds::resolve(id)?;
+        Ok(AppPath::xray_server_dir()?.join(id).with_extension("json"))
+    }
+
+    pub fn read(id: &str) -> Result<Value> {
+        let path = Servers::resolve(id)?;
+        let json_str = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read server config {}", id))?;
+        Ok(serde_json::from_str(json_str.as_str())?)
+    }
+
+    /* 按已有的 outbound 替换变量后生成服务器配置, 不修改客户端的文件
+     * reality 的私钥依次用: 传入的, 上次生成的, 都没有时先占位 */
+    pub fn generate(app: &AppHandle, id: &str, private_key: Option<String>) -> Result<ServerPair> {
+        let outbound = Variables::substitute(app, &Outbounds::read(id)?)?;
+        let outbounds = outbounds_of(&outbound, id)?;
+        let index = main_index(&outbounds, "proxy");
+
+        let mut private_key = private_key.filter(|key| !key.is_empty());
+        let mut missing_private_key = false;
+        if let Some(reality) = outbounds[index].pointer("/streamSettings/realitySettings") {
+            let public_key = reality.get("publicKey").and_then(|key| key.as_str());
+            let matches =
+                |key: &String| public_key.is_some() && public_key_of(key).as_deref() == public_key;
+            if let Some(key) = &private_key {
+                if !matches(key) {
+                    anyhow::bail!("private key does not match the publicKey of {}", id);
+                }
+            } else {
+                private_key = Servers::read(id)
+                    .ok()
+                    .and_then(|server| {
+                        server
+                            .pointer("/inbounds/0/streamSettings/realitySettings/privateKey")?
+                            .as_str()
+                            .map(|key| key.to_string())
+                    })
+                    .filter(matches);
+            }
+            if private_key.is_none() {
+                log::warn!(target: "app", "[server]: no private key for {id}, use a placeholder");
+                private_key = Some(PRIVATE_KEY_PLACEHOLDER.to_string());
+                missing_private_key = true;
+            }
+        }
+
+        let inbound = inbound_of(&outbounds[index], private_key.as_deref())?;
+        Servers::write(id, outbound, inbound, missing_private_key)
+    }
+
+    /* 换一对 reality 密钥, 客户端和服务器配置一起写, 服务器要换上新的配置 */
+    pub fn rotate_keys(app: &AppHandle, id: &str) -> Result<ServerPair> {
+        let mut outbound = Outbounds::read(id)?;
+        let outbounds = outbounds_of(&outbound, id)?;
+        let index = main_index(&outbounds, "proxy");
+        if outbounds[index]
+            .pointer("/streamSettings/realitySettings")
+            .is_none()
+        {
+            anyhow::bail!("outbound {} does not use reality", id);
+        }
+
+        let pair = x25519_pair();
+        outbound["outbounds"][index]["streamSettings"]["realitySettings"]["publicKey"] =
+            Value::from(pair.public_key);
+        // 客户端文件保留变量, 服务器配置用替换后的值
+        let resolved = Variables::substitute(app, &outbound)?;
+        let inbound = inbound_of(&resolved["outbounds"][index], Some(&pair.private_key))?;
+        Outbounds::replace(app, id, &outbound)?;
+        Servers::write(id, outbound, inbound, false)
+    }
+
+    /* 新建节点: 新的 uuid, reality 时新的密钥对和 shortId */
+    pub fn create(app: &AppHandle, node: NewNode) -> Result<ServerPair> {
+        if node.address.is_empty() || node.server_name.is_empty() {
+            anyhow::bail!("server address and name are required");
+        }
+        let mut private_key = None;
+        let (security_key, security_settings) = match node.security {
+            Security::Tls => (
+                "tlsSettings",
+                json!({ "serverName": node.server_name, "fingerprint": "chrome" }),
+            ),
+            Security::Reality => {
+                let pair = x25519_pair();
+                private_key = Some(pair.private_key);
+                (
+                    "realitySettings",
+                    json!({
+                        "serverName": node.server_name,
+                        "fingerprint": "chrome",
+                        "publicKey": pair.public_key,
+                        "shortId": short_id(),
+                        "spiderX": ""
+                    }),
+                )
+            }
+            Security::None => anyhow::bail!("vless node requires tls or reality"),
+        };
+
+        let outbound = json!({
+            "outbounds": [{
+                "protocol": "vless",
+                "settings": {
+                    "vnext": [{
+                        "address": node.address,
+                        "port": node.port,
+                        "users": [{
+                            "id": new_uuid(),
+                            "encryption": "none",
+                            "flow": "xtls-rprx-vision"
+                        }]
+                    }]
+                },
+                "streamSettings": {
+                    "network": "tcp",
+                    "security": node.security,
+                    security_key: security_settings
+                },
+                "tag": "proxy"
+            }]
+        });
+        let inbound = inbound_of(&outbound["outbounds"][0], private_key.as_deref())?;
+        Outbounds::create(app, &node.id, &outbound)?;
+        Servers::write(&node.id, outbound, inbound, false)
+    }
+
+    fn write(
+        id: &str,
+        outbound: Value,
+        inbound: Value,
+        missing_private_key: bool,
+    ) -> Result<ServerPair> {
+        let server = json!({
+            "log": { "loglevel": "warning" },
+            "inbounds": [inbound],
+            "outbounds": [
+                { "protocol": "freedom", "tag": "direct" },
+                { "protocol": "blackhole", "tag": "block" }
+            ]
+        });
+
+        let path = Servers::resolve(id)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json_str = serde_json::to_string_pretty(&server)?;
+        Store::write_atomic(&path, json_str.as_bytes())?;
+        Ok(ServerPair {
+            id: id.to_string(),
+            outbound,
+            server,
+            missing_private_key,
+        })
+    }
+}
+
+/* 按客户端的出站生成服务器的入站: 相同的用户, 传输方式和 tls/reality */
+fn inbound_of(outbound: &Value, private_key: Option<&str>) -> Result<Value> {
+    let protocol = outbound
+        .get("protocol")
+        .and_then(|protocol| protocol.as_str())
+        .unwrap_or_default();
+    let settings = outbound.get("settings").cloned().unwrap_or(json!({}));
+    let (port, settings) = match protocol {
+        "vless" | "vmess" => {
+            let server = settings
+                .pointer("/vnext/0")
+                .ok_or(anyhow::anyhow!("{} outbound has no server", protocol))?;
+            let clients: Vec<Value> = server
+                .get("users")
+                .and_then(|users| users.as_array())
+                .cloned()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|user| {
+                    let mut client = Map::new();
+                    for key in ["id", "flow", "email", "level"] {
+                        if let Some(value) = user.get(key).filter(|value| !value.is_null()) {
+                            client.insert(key.to_string(), value.clone());
+                        }
+                    }
+                    Value::Object(client)
+                })
+                .collect();
+            let mut inbound_settings = json!({ "clients": clients });
+            if protocol == "vless" {
+                inbound_settings["decryption"] = Value::from("none");
+            }
+            (server.get("port").cloned(), inbound_settings)
+        }
+        "trojan" => {
+            let server = settings
+                .pointer("/servers/0")
+                .ok_or(anyhow::anyhow!("trojan outbound has no server"))?;
+            let password = server.get("password").cloned().unwrap_or_default();
+            (
+                server.get("port").cloned(),
+                json!({ "clients": [{ "password": password }] }),
+            )
+        }
+        _ => anyhow::bail!("can not generate server config for {} outbound", protocol),
+    };
+
+    // 传输方式原样保留, 只换掉客户端才有的 tls/reality 参数
+    let mut stream_settings = outbound
+        .get("streamSettings")
+        .and_then(|stream_settings| stream_settings.as_object())
+        .cloned()
+        .unwrap_or_default();
+    stream_settings.remove("sockopt");
+    if let Some(Value::Object(tls)) = stream_settings.remove("tlsSettings") {
+        let mut server_tls = json!({
+            "certificates": [{ "certificateFile": CERTIFICATE_FILE, "keyFile": KEY_FILE }]
+        });
+        if let Some(alpn) = tls.get("alpn") {
+            server_tls["alpn"] = alpn.clone();
+        }
+        stream_settings.insert("tlsSettings".to_string(), server_tls);
+    }
+    if let Some(Value::Object(reality)) = stream_settings.remove("realitySettings") {
+        let private_key = private_key.ok_or(anyhow::anyhow!("reality requires a private key"))?;
+        let server_name = reality
+            .get("serverName")
+            .and_then(|name| name.as_str())
+            .unwrap_or_default();
+        let short_id = reality.get("shortId").cloned().unwrap_or(Value::from(""));
+        stream_settings.insert(
+            "realitySettings".to_string(),
+            json!({
+                "show": false,
+                "dest": format!("{}:443", server_name),
+                "xver": 0,
+                "serverNames": [server_name],
+                "privateKey": private_key,
+                "shortIds": [short_id]
+            }),
+        );
+    }
+
+    Ok(json!({
+        "tag": format!("{}-in", protocol),
+        "listen": "0.0.0.0",
+        "port": port.unwrap_or(Value::from(443)),
+        "protocol": protocol,
+        "settings": settings,
+        "streamSettings": stream_settings
+    }))
+}
+
+pub fn new_uuid() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/* 私钥按 x25519 的要求处理后编码, 和 xray x25519 生成的一致 */
+pub fn x25519_pair() -> X25519Pair {
+    let mut private_key = StaticSecret::random_from_rng(OsRng).to_bytes();
+    private_key[0] &= 248;
+    private_key[31] &= 127;
+    private_key[31] |= 64;
+    let secret = StaticSecret::from(private_key);
+    X25519Pair {
+        private_key: encode_key(&secret.to_bytes()),
+        public_key: encode_key(PublicKey::from(&secret).as_bytes()),
+    }
+}
+
+/* 由私钥算出公钥 */
+pub fn public_key_of(private_key: &str) -> Option<String> {
+    let bytes: [u8; 32] = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(private_key)
+        .ok()?
+        .try_into()
+        .ok()?;
+    let secret = StaticSecret::from(bytes);
+    Some(encode_key(PublicKey::from(&secret).as_bytes()))
+}
+
+/* reality 的 shortId, 8 字节的十六进制 */
+pub fn short_id() -> String {
+    let mut bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn encode_key(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
@@ -0,0 +1,570 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::Path;
+use tauri::AppHandle;
+
+use super::config::IConfig;
+use super::format::{read_config, ConfigFormat};
+use super::geodata::{
+    is_subdomain, Cidr, GeoData, GeoDomain, RegexCache, GEOIP_FILE, GEOSITE_FILE,
+};
+use super::path::AppPath;
+use super::routing::Routings;
+use super::schema::{DomainStrategy, RuleObject};
+use super::template::RoutingTemplates;
+
+/* 模拟一次请求 */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteQuery {
+    // 域名或 ip
+    pub target: String,
+    pub port: u16,
+    #[serde(default)]
+    pub inbound_tag: Option<String>,
+    // tcp 或 udp, 默认 tcp
+    #[serde(default)]
+    pub network: Option<String>,
+    // 指定解析结果, 为空时用系统 dns 解析
+    #[serde(default)]
+    pub ips: Option<Vec<IpAddr>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteResult {
+    // 命中的规则在路由文件或规则块里的下标, 为空表示走默认出站
+    pub rule_index: Option<usize>,
+    // 命中规则块里的规则时为规则块的 id
+    pub block: Option<String>,
+    pub rule_tag: Option<String>,
+    pub outbound_tag: Option<String>,
+    pub balancer_tag: Option<String>,
+    // 命中的 domain/ip 条目
+    pub matched_by: Option<String>,
+    pub resolved_ips: Vec<IpAddr>,
+}
+
+/* 按 xray 的规则顺序和 domainStrategy 模拟路由 */
+pub struct RouteSimulator {
+    domain: Option<String>,
+    ips: Option<Vec<IpAddr>>,
+    // 调用方指定的解析结果, 按 domainStrategy 在解析时才用
+    given_ips: Option<Vec<IpAddr>>,
+    port: u16,
+    network: String,
+    inbound_tag: Option<String>,
+    // 同一次模拟里 geosite/geoip 只读一次
+    geosite_cache: HashMap<(String, String), Vec<GeoDomain>>,
+    geoip_cache: HashMap<(String, String), (Vec<Cidr>, bool)>,
+    regexes: RegexCache,
+}
+
+impl RouteSimulator {
+    /* 模拟当前使用的路由, name 不为空时用指定的路由文件 */
+    pub fn simulate(
+        app: &AppHandle,
+        query: RouteQuery,
+        name: Option<String>,
+    ) -> Result<RouteResult> {
+        let active_routing = IConfig::active_routing(app);
+        let name = name
+            .or(active_routing.clone())
+            .filter(|name| !name.is_empty())
+            .ok_or(anyhow::anyhow!("no active routing"))?;
+        let routing = Routings::read_resolved(app, &name)?.routing;
+        let domain_strategy = routing.domain_strategy.unwrap_or(DomainStrategy::AsIs);
+
+        // 当前路由和暂存时一样把选择的规则块插到同样的位置, 记下每条规则的来源
+        let mut rules = routing.rules;
+        let mut sources: Vec<(Option<String>, usize)> =
+            (0..rules.len()).map(|index| (None, index)).collect();
+        if active_routing.as_deref() == Some(name.as_str()) {
+            let mut block_rules = Vec::new();
+            let mut block_sources = Vec::new();
+            for (id, rules) in RoutingTemplates::active_rules(app) {
+                for (index, rule) in rules.into_iter().enumerate() {
+                    block_sources.push((Some(id.clone()), index));
+                    block_rules.push(rule);
+                }
+            }
+            let index = RoutingTemplates::block_index(
+                &rules,
+                RoutingTemplates::position(app),
+                RuleObject::is_catch_all,
+            );
+            rules.splice(index..index, block_rules);
+            sources.splice(index..index, block_sources);
+        }
+
+        let mut simulator = RouteSimulator::new(query);
+        let mut result = match simulator.route(&rules, domain_strategy)? {
+            Some((index, matched_by)) => {
+                let rule = &rules[index];
+                let (block, rule_index) = sources[index].clone();
+                RouteResult {
+                    rule_index: Some(rule_index),
+                    block,
+                    rule_tag: rule.rule_tag.clone(),
+                    outbound_tag: rule.outbound_tag.clone(),
+                    balancer_tag: rule.balancer_tag.clone(),
+                    matched_by,
+                    ..RouteResult::default()
+                }
+            }
+            // 没有命中规则时走第一个出站
+            None => RouteResult {
+                outbound_tag: RouteSimulator::default_outbound()?,
+                ..RouteResult::default()
+            },
+        };
+        result.resolved_ips = simulator.ips.unwrap_or_default();
+        Ok(result)
+    }
+
+    fn new(query: RouteQuery) -> RouteSimulator {
+        let target = query.target.trim().trim_matches(['[', ']']).to_lowercase();
+        let (domain, ips) = match target.parse::<IpAddr>() {
+            Ok(ip) => (None, Some(vec![ip])),
+            Err(_) => (Some(target), None),
+        };
+        RouteSimulator {
+            domain,
+            ips,
+            given_ips: query.ips,
+            port: query.port,
+            network: query.network.unwrap_or("tcp".to_string()),
+            inbound_tag: query.inbound_tag,
+            geosite_cache: HashMap::new(),
+            geoip_cache: HashMap::new(),
+            regexes: RegexCache::default(),
+        }
+    }
+
+    /* 命中的规则下标和条目 */
+    fn route(
+        &mut self,
+        rules: &[RuleObject],
+        domain_strategy: DomainStrategy,
+    ) -> Result<Option<(usize, Option<String>)>> {
+        // IPOnDemand 在遇到 ip 规则时解析, AsIs 不解析
+        let matched = self.first_match(rules, domain_strategy)?;
+        // IPIfNonMatch 没有命中时解析域名再匹配一次
+        if matched.is_none()
+            && domain_strategy == DomainStrategy::IPIfNonMatch
+            && self.ips.is_none()
+        {
+            self.resolve();
+            return self.first_match(rules, domain_strategy);
+        }
+        Ok(matched)
+    }
+
+    fn first_match(
+        &mut self,
+        rules: &[RuleObject],
+        domain_strategy: DomainStrategy,
+    ) -> Result<Option<(usize, Option<String>)>> {
+        for (index, rule) in rules.iter().enumerate() {
+            if !rule.enabled {
+                continue;
+            }
+            if let Some(matched_by) = self.match_rule(rule, domain_strategy)? {
+                return Ok(Some((index, matched_by)));
+            }
+        }
+        Ok(None)
+    }
+
+    /* 规则里的条件都满足才算命中, 返回命中的 domain/ip 条目 */
+    fn match_rule(
+        &mut self,
+        rule: &RuleObject,
+        domain_strategy: DomainStrategy,
+    ) -> Result<Option<Option<String>>> {
+        // 模拟不了的条件按不命中处理
+        if rule.source.is_some()
+            || rule.user.is_some()
+            || rule.protocol.is_some()
+            || rule.source_port.is_some()
+            || rule.extra.contains_key("attrs")
+        {
+            return Ok(None);
+        }
+
+        if let Some(network) = &rule.network {
+            if !network
+                .split(',')
+                .any(|network| network.trim() == self.network)
+            {
+                return Ok(None);
+            }
+        }
+        if let Some(port) = &rule.port {
+            if !port
+                .ranges()?
+                .iter()
+                .any(|(from, to)| (*from..=*to).contains(&self.port))
+            {
+                return Ok(None);
+            }
+        }
+        if let Some(inbound_tags) = &rule.inbound_tag {
+            match &self.inbound_tag {
+                Some(tag) if inbound_tags.contains(tag) => {}
+                _ => return Ok(None),
+            }
+        }
+
+        let mut matched_by = None;
+        if let Some(domains) = &rule.domain {
+            let Some(domain) = self.domain.clone() else {
+                return Ok(None);
+            };
+            let mut found = None;
+            for matcher in domains {
+                if self.match_domain(matcher, &domain)? {
+                    found = Some(matcher.clone());
+                    break;
+                }
+            }
+            match found {
+                Some(matcher) => matched_by = Some(matcher),
+                None => return Ok(None),
+            }
+        }
+        if let Some(ip_matchers) = &rule.ip {
+            if self.ips.is_none() && domain_strategy == DomainStrategy::IPOnDemand {
+                self.resolve();
+            }
+            let ips = self.ips.clone().unwrap_or_default();
+            let mut found = None;
+            for matcher in ip_matchers {
+                if self.match_ip(matcher, &ips)? {
+                    found = Some(matcher.clone());
+                    break;
+                }
+            }
+            match found {
+                Some(matcher) => matched_by = Some(matcher),
+                None => return Ok(None),
+            }
+        }
+
+        Ok(Some(matched_by))
+    }
+
+    /* domain:, full:, regexp:, keyword:, geosite:, ext:, 没有前缀按关键字 */
+    fn match_domain(&mut self, matcher: &str, domain: &str) -> Result<bool> {
+        let matched = match matcher.split_once(':') {
+            Some(("domain", value)) => is_subdomain(domain, &value.to_lowercase()),
+            Some(("full", value)) => domain == value.to_lowercase(),
+            Some(("keyword", value)) => domain.contains(value.to_lowercase().as_str()),
+            Some(("regexp", value)) => self.regexes.get(value)?.is_match(domain),
+            Some(("geosite", value)) => self.match_geosite(GEOSITE_FILE, value, domain)?,
+            Some(("ext", value)) => {
+                let (file, code) = value
+                    .split_once(':')
+                    .ok_or(anyhow::anyhow!("invalid matcher {}", matcher))?;
+                self.match_geosite(file, code, domain)?
+            }
+            _ => domain.contains(matcher.to_lowercase().as_str()),
+        };
+        Ok(matched)
+    }
+
+    /* geosite:google@cn 只匹配带 cn 属性的域名 */
+    fn match_geosite(&mut self, file: &str, code: &str, domain: &str) -> Result<bool> {
+        let (code, attribute) = code.split_once('@').unwrap_or((code, ""));
+        let key = (file.to_string(), code.to_lowercase());
+        if !self.geosite_cache.contains_key(&key) {
+            let domains = GeoData::load_site(file, code)?;
+            self.geosite_cache.insert(key.clone(), domains);
+        }
+        let regexes = &mut self.regexes;
+        Ok(self.geosite_cache[&key].iter().any(|geo_domain| {
+            (attribute.is_empty() || geo_domain.attributes.iter().any(|attr| attr == attribute))
+                && geo_domain.matches(domain, regexes)
+        }))
+    }
+
+    /* cidr, ip, geoip:cn, geoip:!cn, ext:file.dat:tag */
+    fn match_ip(&mut self, matcher: &str, ips: &[IpAddr]) -> Result<bool> {
+        let (file, code) = match matcher.split_once(':') {
+            Some(("geoip", code)) => (GEOIP_FILE, code),
+            Some(("ext", value)) => value
+                .split_once(':')
+                .ok_or(anyhow::anyhow!("invalid matcher {}", matcher))?,
+            _ => {
+                let cidr = Cidr::parse(matcher).ok_or(anyhow::anyhow!("invalid ip {}", matcher))?;
+                return Ok(ips.iter().any(|ip| cidr.contains(ip)));
+            }
+        };
+
+        let (code, negate) = match code.strip_prefix('!') {
+            Some(code) => (code, true),
+            None => (code, false),
+        };
+        let key = (file.to_string(), code.to_lowercase());
+        if !self.geoip_cache.contains_key(&key) {
+            let cidrs = GeoData::load_ip(file, code)?;
+            self.geoip_cache.insert(key.clone(), cidrs);
+        }
+        let (cidrs, reverse_match) = &self.geoip_cache[&key];
+        Ok(ips
+            .iter()
+            .any(|ip| cidrs.iter().any(|cidr| cidr.contains(ip)) != (*reverse_match != negate)))
+    }
+
+    fn resolve(&mut self) {
+        if let Some(ips) = self.given_ips.take() {
+            self.ips = Some(ips);
+            return;
+        }
+        let ips = self
+            .domain
+            .as_deref()
+            .and_then(|domain| (domain, self.port).to_socket_addrs().ok())
+            .map(|addrs| addrs.map(|addr| addr.ip()).collect())
+            .unwrap_or_default();
+        self.ips = Some(ips);
+    }
+
+    /* 暂存 confdir 里的第一个出站, 文件名带 tail 的追加到最后, 其它的插到最前 */
+    fn default_outbound() -> Result<Option<String>> {
+        let confdir = AppPath::xray_temp_config_dir()?;
+        if !confdir.is_dir() {
+            return Ok(None);
+        }
+        let mut paths: Vec<_> = fs::read_dir(&confdir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| ConfigFormat::is_config_file(path))
+            .collect();
+        paths.sort();
+
+        let mut files = Vec::new();
+        for path in paths {
+            let file_name = path
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .unwrap_or_default()
+                .to_string();
+            files.push((file_name, outbound_tags(&path)?));
+        }
+        Ok(first_outbound(files))
+    }
+}
+
+/* 按文件名顺序合并出站, 和 xray 一样带 tail 的追加到最后, 其它的插到最前 */
+fn first_outbound(files: Vec<(String, Vec<String>)>) -> Option<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for (file_name, file_tags) in files {
+        if file_name.contains("tail") {
+            tags.extend(file_tags);
+        } else {
+            tags.splice(0..0, file_tags);
+        }
+    }
+    tags.into_iter().next()
+}
+
+fn outbound_tags(path: &Path) -> Result<Vec<String>> {
+    let value = read_config(path)?;
+    Ok(value
+        .get("outbounds")
+        .and_then(|outbounds| outbounds.as_array())
+        .map(|outbounds| {
+            outbounds
+                .iter()
+                .map(|outbound| {
+                    outbound
+                        .get("tag")
+                        .and_then(|tag| tag.as_str())
+                        .unwrap_or_default()
+                        .to_string()
+                })
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rules(value: serde_json::Value) -> Vec<RuleObject> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    // 指定解析结果, 测试里不走系统 dns
+    fn simulator_for(target: &str, ips: &[&str]) -> RouteSimulator {
+        RouteSimulator::new(RouteQuery {
+            target: target.to_string(),
+            port: 443,
+            inbound_tag: None,
+            network: None,
+            ips: Some(ips.iter().map(|ip| ip.parse().unwrap()).collect()),
+        })
+    }
+
+    fn matched_index(
+        simulator: &mut RouteSimulator,
+        rules: &[RuleObject],
+        domain_strategy: DomainStrategy,
+    ) -> Option<usize> {
+        simulator
+            .route(rules, domain_strategy)
+            .unwrap()
+            .map(|(index, _)| index)
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = rules(json!([
+            { "domain": ["keyword:nothing"], "outboundTag": "block" },
+            { "domain": ["domain:example.com"], "outboundTag": "direct", "enabled": false },
+            { "domain": ["domain:example.com"], "outboundTag": "proxy" },
+            { "network": "tcp,udp", "outboundTag": "direct" }
+        ]));
+        let mut simulator = simulator_for("www.example.com", &[]);
+        assert_eq!(
+            simulator.route(&rules, DomainStrategy::AsIs).unwrap(),
+            Some((2, Some("domain:example.com".to_string())))
+        );
+    }
+
+    #[test]
+    fn as_is_does_not_resolve() {
+        let rules = rules(json!([{ "ip": ["10.0.0.0/8"], "outboundTag": "direct" }]));
+        let mut simulator = simulator_for("example.com", &["10.0.0.1"]);
+        assert_eq!(
+            matched_index(&mut simulator, &rules, DomainStrategy::AsIs),
+            None
+        );
+        assert!(simulator.ips.is_none());
+    }
+
+    #[test]
+    fn ip_if_non_match_resolves_in_second_pass() {
+        let rules = rules(json!([
+            { "ip": ["10.0.0.0/8"], "outboundTag": "direct" },
+            { "domain": ["full:example.com"], "outboundTag": "proxy" }
+        ]));
+        // 域名规则先命中, 不解析
+        let mut simulator = simulator_for("example.com", &["10.0.0.1"]);
+        assert_eq!(
+            matched_index(&mut simulator, &rules, DomainStrategy::IPIfNonMatch),
+            Some(1)
+        );
+        assert!(simulator.ips.is_none());
+        // 没有命中时解析后再匹配一次
+        let mut simulator = simulator_for("other.com", &["10.0.0.1"]);
+        assert_eq!(
+            matched_index(&mut simulator, &rules, DomainStrategy::IPIfNonMatch),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn ip_on_demand_resolves_at_first_ip_rule() {
+        let rules = rules(json!([
+            { "ip": ["10.0.0.0/8"], "outboundTag": "direct" },
+            { "domain": ["full:example.com"], "outboundTag": "proxy" }
+        ]));
+        let mut simulator = simulator_for("example.com", &["10.0.0.1"]);
+        assert_eq!(
+            matched_index(&mut simulator, &rules, DomainStrategy::IPOnDemand),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn domain_matchers() {
+        let mut simulator = simulator_for("www.example.com", &[]);
+        let domain = "www.example.com";
+        let cases = [
+            ("keyword:ample", true),
+            ("ample", true),
+            ("full:www.example.com", true),
+            ("full:example.com", false),
+            ("domain:example.com", true),
+            ("domain:www.example.com", true),
+            ("domain:ample.com", false),
+            ("regexp:^www\\.example\\.", true),
+            ("regexp:^example", false),
+        ];
+        for (matcher, expected) in cases {
+            assert_eq!(
+                simulator.match_domain(matcher, domain).unwrap(),
+                expected,
+                "{matcher}"
+            );
+        }
+        assert!(simulator.match_domain("regexp:(", domain).is_err());
+    }
+
+    #[test]
+    fn geoip_negation_and_reverse_match() {
+        let mut simulator = simulator_for("1.0.1.1", &[]);
+        let cn = vec![Cidr::parse("1.0.1.0/24").unwrap()];
+        let inside: Vec<IpAddr> = vec!["1.0.1.1".parse().unwrap()];
+        let outside: Vec<IpAddr> = vec!["8.8.8.8".parse().unwrap()];
+        let key = (GEOIP_FILE.to_string(), "cn".to_string());
+
+        simulator
+            .geoip_cache
+            .insert(key.clone(), (cn.clone(), false));
+        assert!(simulator.match_ip("geoip:cn", &inside).unwrap());
+        assert!(!simulator.match_ip("geoip:cn", &outside).unwrap());
+        assert!(!simulator.match_ip("geoip:!cn", &inside).unwrap());
+        assert!(simulator.match_ip("geoip:!cn", &outside).unwrap());
+
+        // 反向匹配的分类, 不在列表里的 ip 才算命中
+        simulator.geoip_cache.insert(key, (cn, true));
+        assert!(!simulator.match_ip("geoip:cn", &inside).unwrap());
+        assert!(simulator.match_ip("geoip:cn", &outside).unwrap());
+        assert!(simulator.match_ip("geoip:!cn", &inside).unwrap());
+    }
+
+    #[test]
+    fn first_outbound_prepends_and_appends_tail() {
+        let files = |names: &[(&str, &[&str])]| -> Vec<(String, Vec<String>)> {
+            names
+                .iter()
+                .map(|(name, tags)| {
+                    (
+                        name.to_string(),
+                        tags.iter().map(|tag| tag.to_string()).collect(),
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(
+            first_outbound(files(&[
+                ("06_outbounds.json", &["direct", "block"]),
+                ("98.outbounds.tail.json", &["proxy"]),
+            ])),
+            Some("direct".to_string())
+        );
+        // 后面的非 tail 文件插到最前
+        assert_eq!(
+            first_outbound(files(&[
+                ("06_outbounds.json", &["direct"]),
+                ("07_outbounds.json", &["dns-out", "other"]),
+            ])),
+            Some("dns-out".to_string())
+        );
+        assert_eq!(
+            first_outbound(files(&[
+                ("97.outbounds.loaded.tail.json", &["loaded"]),
+                ("98.outbounds.tail.json", &["proxy"]),
+            ])),
+            Some("loaded".to_string())
+        );
+        assert_eq!(first_outbound(Vec::new()), None);
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::Result;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use tauri::{AppHandle, Manager, State};
+
+use super::config::{IConfig, PortConfig, UserConfigValue};
+
+/* 配置变化的订阅者, 参数为 (app, 旧配置, 新配置) */
+pub type Listener = Arc<dyn Fn(&AppHandle, &UserConfigValue, &UserConfigValue) + Send + Sync>;
+
+pub struct AppState {
+    config: RwLock<UserConfigValue>,
+    // 修改依次进行, 落盘时不持有 config 的锁, 读取不用等待写文件
+    update_lock: Mutex<()>,
+    port_config: RwLock<PortConfig>,
+    listeners: Mutex<Vec<Listener>>,
+}
+
+impl AppState {
+    pub fn init(app: &AppHandle, config: UserConfigValue, port_config: PortConfig) -> Result<()> {
+        let managed = app.manage(AppState {
+            config: RwLock::new(config),
+            update_lock: Mutex::new(()),
+            port_config: RwLock::new(port_config),
+            listeners: Mutex::new(Vec::new()),
+        });
+        if !managed {
+            anyhow::bail!("app state already initialized");
+        }
+        Ok(())
+    }
+
+    pub fn get(app: &AppHandle) -> Result<State<'_, AppState>> {
+        app.try_state::<AppState>()
+            .ok_or(anyhow::anyhow!("app state not initialized"))
+    }
+
+    // 修改都是整体替换, 锁中毒时里面的数据仍然是完整的
+    pub fn snapshot(&self) -> UserConfigValue {
+        self.config
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn port_config(&self) -> PortConfig {
+        self.port_config
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn set_port_config(&self, port_config: PortConfig) {
+        *self
+            .port_config
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = port_config;
+    }
+
+    /* 原子地修改多个字段, 落盘成功后才生效, 然后通知订阅者
+     * 在副本上修改并落盘, 只在替换时持有写锁 */
+    pub fn update<F>(&self, app: &AppHandle, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut UserConfigValue),
+    {
+        let (old_config, new_config) = {
+            let _update = self
+                .update_lock
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let old_config = self.snapshot();
+            let mut new_config = old_config.clone();
+            f(&mut new_config);
+            if new_config == old_config {
+                return Ok(());
+            }
+            IConfig::persist(&new_config)?;
+            *self.config.write().unwrap_or_else(PoisonError::into_inner) = new_config.clone();
+            (old_config, new_config)
+        };
+
+        self.notify(app, &old_config, &new_config);
+        Ok(())
+    }
+
+    pub fn subscribe(&self, listener: Listener) {
+        self.listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(listener);
+    }
+
+    fn notify(&self, app: &AppHandle, old_config: &UserConfigValue, new_config: &UserConfigValue) {
+        // 先复制一份, 订阅者里可以再次修改配置
+        let listeners = self
+            .listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        for listener in listeners {
+            listener(app, old_config, new_config);
+        }
+    }
+}
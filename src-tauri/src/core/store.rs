@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Store {}
+
+impl Store {
+    /* 先写临时文件, fsync 后 rename, 避免写一半留下截断的文件 */
+    pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+        let dir = path
+            .parent()
+            .ok_or(anyhow::anyhow!("invalid path {}", path.display()))?;
+        let file_name = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .ok_or(anyhow::anyhow!("invalid path {}", path.display()))?;
+        let temp_path = dir.join(format!(".{}.tmp", file_name));
+
+        let mut file = File::create(&temp_path)
+            .with_context(|| format!("failed to create {}", temp_path.display()))?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&temp_path, path)
+            .with_context(|| format!("failed to rename to {}", path.display()))?;
+
+        // rename 本身也要落盘
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+
+        Ok(())
+    }
+
+    /* 复制一份到备份目录, 只保留最新的 keep 份 */
+    pub fn backup(path: &Path, backup_dir: &Path, keep: usize) -> Result<()> {
+        if !backup_dir.exists() {
+            fs::create_dir_all(backup_dir)?;
+        }
+
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or(anyhow::anyhow!("invalid path {}", path.display()))?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let backup_path = backup_dir.join(format!("{}-{}.json", stem, timestamp));
+        fs::copy(path, &backup_path)
+            .with_context(|| format!("failed to backup {}", path.display()))?;
+
+        for old_backup in Store::backups(path, backup_dir)?.into_iter().skip(keep) {
+            fs::remove_file(old_backup)?;
+        }
+
+        Ok(())
+    }
+
+    /* 备份列表, 新的在前 */
+    pub fn backups(path: &Path, backup_dir: &Path) -> Result<Vec<PathBuf>> {
+        if !backup_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let prefix = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(|stem| format!("{}-", stem))
+            .ok_or(anyhow::anyhow!("invalid path {}", path.display()))?;
+
+        let mut backups: Vec<(u128, PathBuf)> = fs::read_dir(backup_dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter_map(|backup_path| {
+                let timestamp = backup_path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.strip_prefix(prefix.as_str()))
+                    .and_then(|timestamp| timestamp.parse::<u128>().ok())?;
+                Some((timestamp, backup_path))
+            })
+            .collect();
+        backups.sort_by_key(|(timestamp, _)| std::cmp::Reverse(*timestamp));
+
+        Ok(backups
+            .into_iter()
+            .map(|(_, backup_path)| backup_path)
+            .collect())
+    }
+}
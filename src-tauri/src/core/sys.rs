@@ -1,11 +1,11 @@
 use anyhow::{Ok, Result};
 
 use rustem_proxy::SystemProxy;
+use tauri::AppHandle;
 
+use crate::log_err;
 
-
-
-use super::config::IConfig;
+use super::config::{IConfig, UserConfigValue};
 
 #[cfg(target_os = "windows")]
 static DEFAULT_BYPASS: &str = "localhost;127.*;192.168.*;<local>";
@@ -16,9 +16,9 @@ pub struct Sysopt {}
 
 impl Sysopt {
     // pub fn able_proxy() -> Result<()> {
-    pub fn able_proxy() -> Result<()> {
+    pub fn able_proxy(app: &AppHandle) -> Result<()> {
         let port_config =
-            IConfig::port_config().ok_or(anyhow::anyhow!("failed to get port config"))?;
+            IConfig::port_config(app).ok_or(anyhow::anyhow!("failed to get port config"))?;
         let http_port = port_config
             .http_port
             .ok_or(anyhow::anyhow!("failed to get http port"))?;
@@ -57,15 +57,29 @@ impl Sysopt {
         Ok(())
     }
 
-    pub fn sync_proxy() -> Result<()> {
-        let port_enable = IConfig::sys_port_enable()
+    pub fn sync_proxy(app: &AppHandle) -> Result<()> {
+        let port_enable = IConfig::sys_port_enable(app)
             .ok_or(anyhow::anyhow!("failed to get port enable config"))?;
 
         if port_enable {
-            Sysopt::able_proxy()?;
+            Sysopt::able_proxy(app)?;
         } else {
             Sysopt::disable_proxy()?;
         }
         Ok(())
     }
+
+    // 系统代理开关变化时同步
+    pub fn on_config_change(
+        app: &AppHandle,
+        old_config: &UserConfigValue,
+        new_config: &UserConfigValue,
+    ) {
+        if old_config.sys_port_enable != new_config.sys_port_enable
+            || old_config.http_port != new_config.http_port
+            || old_config.socks_port != new_config.socks_port
+        {
+            log_err!(Sysopt::sync_proxy(app));
+        }
+    }
 }
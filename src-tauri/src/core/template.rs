@@ -0,0 +1,391 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use tauri::AppHandle;
+
+use super::config::IConfig;
+use super::path::AppPath;
+use super::routing::Routings;
+use super::schema::RuleObject;
+use super::store::Store;
+use super::xray::Xray;
+
+/* 路由规则块, 按选择的顺序插到当前路由文件的规则里 */
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingBlock {
+    pub id: String,
+    pub name: String,
+    // 内置的规则块不能修改和删除
+    pub builtin: bool,
+    pub rules: Vec<RuleObject>,
+}
+
+/* 自定义规则块的文件内容 */
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct BlockFile {
+    name: String,
+    rules: Vec<RuleObject>,
+}
+
+/* 规则块相对当前路由文件规则的位置 */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BlockPosition {
+    // 文件里自己的规则在上面, 规则块插在第一条匹配所有流量的规则前面
+    #[default]
+    BeforeCatchAll,
+    // 规则块在文件的规则前面
+    Top,
+    // 规则块在文件的规则后面, 文件有匹配所有流量的规则时不会生效
+    Bottom,
+}
+
+pub struct RoutingTemplates {}
+
+impl RoutingTemplates {
+    /* 内置的规则块, tag 和预设的 outbound 一致 */
+    fn builtin() -> Vec<RoutingBlock> {
+        let blocks = [
+            (
+                "private-direct",
+                "Private IPs direct",
+                json!([
+                    { "domain": ["geosite:private"], "outboundTag": "direct" },
+                    { "ip": ["geoip:private"], "outboundTag": "direct" }
+                ]),
+            ),
+            (
+                "ads-block",
+                "Ads block",
+                json!([{ "domain": ["geosite:category-ads-all"], "outboundTag": "block" }]),
+            ),
+            (
+                "cn-direct",
+                "CN sites direct",
+                json!([
+                    { "domain": ["geosite:cn"], "outboundTag": "direct" },
+                    { "ip": ["geoip:cn"], "outboundTag": "direct" }
+                ]),
+            ),
+            (
+                "proxy-rest",
+                "Everything else proxy",
+                json!([{ "network": "tcp,udp", "outboundTag": "proxy" }]),
+            ),
+        ];
+
+        blocks
+            .into_iter()
+            .map(|(id, name, rules)| RoutingBlock {
+                id: id.to_string(),
+                name: name.to_string(),
+                builtin: true,
+                rules: serde_json::from_value(rules).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    fn is_builtin(id: &str) -> bool {
+        RoutingTemplates::builtin()
+            .iter()
+            .any(|block| block.id == id)
+    }
+
+    /* 内置的在前, 自定义的按 id 排序 */
+    pub fn list() -> Result<Vec<RoutingBlock>> {
+        let mut blocks = RoutingTemplates::builtin();
+        let block_dir = AppPath::xray_routing_block_dir()?;
+        if !block_dir.is_dir() {
+            return Ok(blocks);
+        }
+
+        let mut custom = Vec::new();
+        for entry in fs::read_dir(&block_dir)? {
+            let path = entry?.path();
+            if path.extension() != Some("json".as_ref()) {
+                continue;
+            }
+            match RoutingTemplates::read(&path) {
+                Ok(block) => custom.push(block),
+                Err(err) => log::warn!(target: "app", "[routing block]: {err}"),
+            }
+        }
+        custom.sort_by(|a, b| a.id.cmp(&b.id));
+        blocks.extend(custom);
+        Ok(blocks)
+    }
+
+    pub fn get(id: &str) -> Result<RoutingBlock> {
+        if let Some(block) = RoutingTemplates::builtin()
+            .into_iter()
+            .find(|block| block.id == id)
+        {
+            return Ok(block);
+        }
+        let path = RoutingTemplates::resolve(id)?;
+        if !path.is_file() {
+            anyhow::bail!("routing block {} not found", id);
+        }
+        RoutingTemplates::read(&path)
+    }
+
+    /* 保存自定义规则块, 正在使用的会重启 xray */
+    pub fn save(app: &AppHandle, id: &str, name: &str, rules: Vec<RuleObject>) -> Result<()> {
+        if RoutingTemplates::is_builtin(id) {
+            anyhow::bail!("can not modify builtin routing block {}", id);
+        }
+        for (index, rule) in rules.iter().enumerate() {
+            rule.validate()
+                .map_err(|err| anyhow::anyhow!("rules[{}]: {}", index, err))?;
+        }
+
+        let path = RoutingTemplates::resolve(id)?;
+        if let Some(block_dir) = path.parent() {
+            fs::create_dir_all(block_dir)?;
+        }
+        let block_file = BlockFile {
+            name: name.to_string(),
+            rules,
+        };
+        let json_str = serde_json::to_string_pretty(&block_file)?;
+        Store::write_atomic(&path, json_str.as_bytes())?;
+
+        if RoutingTemplates::active(app)
+            .iter()
+            .any(|active| active == id)
+        {
+            Xray::reload_xray(app)?;
+        }
+        Ok(())
+    }
+
+    /* 删除时一起从选择里去掉 */
+    pub fn delete(app: &AppHandle, id: &str) -> Result<()> {
+        if RoutingTemplates::is_builtin(id) {
+            anyhow::bail!("can not delete builtin routing block {}", id);
+        }
+        let path = RoutingTemplates::resolve(id)?;
+        if !path.is_file() {
+            anyhow::bail!("routing block {} not found", id);
+        }
+        fs::remove_file(path)?;
+        IConfig::update(app, |config| {
+            config.routing_blocks.retain(|active| active != id)
+        })
+    }
+
+    /* 选择的规则块, 按顺序生效 */
+    pub fn active(app: &AppHandle) -> Vec<String> {
+        IConfig::snapshot(app)
+            .map(|config| config.routing_blocks)
+            .unwrap_or_default()
+    }
+
+    pub fn set_active(app: &AppHandle, ids: Vec<String>) -> Result<()> {
+        for id in &ids {
+            RoutingTemplates::get(id)?;
+        }
+        IConfig::update(app, |config| config.routing_blocks = ids)
+    }
+
+    pub fn position(app: &AppHandle) -> BlockPosition {
+        IConfig::snapshot(app)
+            .map(|config| config.routing_block_position)
+            .unwrap_or_default()
+    }
+
+    pub fn set_position(app: &AppHandle, position: BlockPosition) -> Result<()> {
+        IConfig::update(app, |config| config.routing_block_position = position)
+    }
+
+    /* 规则块插到文件规则里的位置, 暂存和模拟路由共用, 两边的顺序要一致 */
+    pub fn block_index<T, F>(rules: &[T], position: BlockPosition, is_catch_all: F) -> usize
+    where
+        F: Fn(&T) -> bool,
+    {
+        match position {
+            BlockPosition::Top => 0,
+            BlockPosition::Bottom => rules.len(),
+            BlockPosition::BeforeCatchAll => {
+                rules.iter().position(is_catch_all).unwrap_or(rules.len())
+            }
+        }
+    }
+
+    /* 选择的规则块里启用的规则, 找不到的规则块跳过 */
+    pub fn active_rules(app: &AppHandle) -> Vec<(String, Vec<RuleObject>)> {
+        RoutingTemplates::active(app)
+            .into_iter()
+            .filter_map(|id| match RoutingTemplates::get(&id) {
+                Ok(block) => Some((id, block.rules)),
+                Err(err) => {
+                    log::warn!(target: "app", "[routing block]: {err}");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /* 暂存给 xray 的路由: 规则块按设置的位置插到当前路由文件的规则里 */
+    pub fn render(app: &AppHandle, routing_path: &Path) -> Result<Value> {
+        let mut value = Routings::render(routing_path)?;
+        let block_rules: Vec<Value> = RoutingTemplates::active_rules(app)
+            .into_iter()
+            .flat_map(|(_, rules)| rules)
+            .filter(|rule| rule.enabled)
+            .map(serde_json::to_value)
+            .collect::<serde_json::Result<_>>()?;
+        if block_rules.is_empty() {
+            return Ok(value);
+        }
+        let position = RoutingTemplates::position(app);
+
+        let Some(routing) = value
+            .get_mut("routing")
+            .and_then(|routing| routing.as_object_mut())
+        else {
+            anyhow::bail!("invalid routing {}", routing_path.display());
+        };
+        if let Some(rules) = routing
+            .entry("rules")
+            .or_insert(Value::Array(Vec::new()))
+            .as_array_mut()
+        {
+            let catch_all = rules.iter().position(is_catch_all);
+            let index = RoutingTemplates::block_index(rules, position, is_catch_all);
+            if let Some(catch_all) = catch_all.filter(|catch_all| *catch_all < index) {
+                log::warn!(
+                    target: "app",
+                    "[routing block]: blocks are behind the catch-all rules[{}] of {} and will never match",
+                    catch_all,
+                    routing_path.display()
+                );
+            }
+            let block_count = block_rules.len();
+            rules.splice(index..index, block_rules);
+            // 规则块自己的匹配所有流量的规则后面还有规则
+            if let Some(catch_all) = rules[index..index + block_count]
+                .iter()
+                .position(is_catch_all)
+                .map(|offset| index + offset)
+                .filter(|catch_all| catch_all + 1 < rules.len())
+            {
+                log::warn!(
+                    target: "app",
+                    "[routing block]: {} rules after the catch-all block rule will never match",
+                    rules.len() - catch_all - 1
+                );
+            }
+        }
+        Ok(value)
+    }
+
+    /* 自定义规则块的 id 就是文件名 */
+    fn resolve(id: &str) -> Result<PathBuf> {
+        let relative = Path::new(id);
+        let mut components = relative.components();
+        let is_file_name =
+            matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+        if !is_file_name || id.contains('.') {
+            anyhow::bail!("invalid routing block id {}", id);
+        }
+        Ok(AppPath::xray_routing_block_dir()?.join(format!("{}.json", id)))
+    }
+
+    fn read(path: &Path) -> Result<RoutingBlock> {
+        let json_str = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let block_file: BlockFile = serde_json::from_str(json_str.as_str())
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        let id = path
+            .file_stem()
+            .and_then(|file_stem| file_stem.to_str())
+            .unwrap_or_default()
+            .to_string();
+        Ok(RoutingBlock {
+            id,
+            name: block_file.name,
+            builtin: false,
+            rules: block_file.rules,
+        })
+    }
+}
+
+fn is_catch_all(rule: &Value) -> bool {
+    serde_json::from_value::<RuleObject>(rule.clone()).is_ok_and(|rule| rule.is_catch_all())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> Vec<RuleObject> {
+        serde_json::from_value(json!([
+            { "domain": ["geosite:private"], "outboundTag": "direct" },
+            { "network": "tcp,udp", "outboundTag": "proxy", "enabled": false },
+            { "ip": ["geoip:cn"], "outboundTag": "direct" },
+            { "network": "tcp,udp", "outboundTag": "proxy" },
+            { "domain": ["example.com"], "outboundTag": "block" }
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn block_index_before_catch_all() {
+        let rules = rules();
+        // 禁用的匹配所有流量的规则不算
+        assert_eq!(
+            RoutingTemplates::block_index(
+                &rules,
+                BlockPosition::BeforeCatchAll,
+                RuleObject::is_catch_all
+            ),
+            3
+        );
+        assert_eq!(
+            RoutingTemplates::block_index(
+                &rules[..3],
+                BlockPosition::BeforeCatchAll,
+                RuleObject::is_catch_all
+            ),
+            3
+        );
+    }
+
+    #[test]
+    fn block_index_top() {
+        assert_eq!(
+            RoutingTemplates::block_index(&rules(), BlockPosition::Top, RuleObject::is_catch_all),
+            0
+        );
+    }
+
+    #[test]
+    fn block_index_bottom() {
+        assert_eq!(
+            RoutingTemplates::block_index(
+                &rules(),
+                BlockPosition::Bottom,
+                RuleObject::is_catch_all
+            ),
+            5
+        );
+    }
+
+    #[test]
+    fn block_index_same_for_staged_values() {
+        // 暂存时去掉了禁用的规则, 插入的位置仍然在同一条规则前面
+        let values: Vec<Value> = rules()
+            .into_iter()
+            .filter(|rule| rule.enabled)
+            .map(|rule| serde_json::to_value(rule).unwrap())
+            .collect();
+        assert_eq!(
+            RoutingTemplates::block_index(&values, BlockPosition::BeforeCatchAll, is_catch_all),
+            2
+        );
+    }
+}
@@ -1,4 +1,12 @@
-use crate::{cmds, core::config::IConfig, log_err};
+use crate::{
+    cmds,
+    core::chain::Chains,
+    core::config::{IConfig, UserConfigValue},
+    core::outbound::OutboundEntry,
+    core::profile::Profile,
+    core::routing::Routings,
+    log_err,
+};
 use anyhow::Result;
 use clipboard_ext::prelude::*;
 use clipboard_ext::x11_fork::ClipboardContext;
@@ -8,13 +16,13 @@ use tauri::{
     SystemTraySubmenu,
 };
 
-use super::{sys::Sysopt, xray};
+use super::xray;
 
 pub struct Tray {}
 
 impl Tray {
     // 托盘菜单
-    pub fn menu() -> SystemTrayMenu {
+    pub fn menu(app: &AppHandle) -> SystemTrayMenu {
         let zh = true;
 
         let version = "0.0.0".to_string();
@@ -31,7 +39,7 @@ impl Tray {
 
         //路由
         let mut router_menu: SystemTrayMenu = SystemTrayMenu::new();
-        let select_router: Option<String> = IConfig::active_routing();
+        let select_router: Option<String> = IConfig::active_routing(app);
         if let Some(router_list) = IConfig::get_routing_list() {
             for pathbuf in router_list {
                 let file_name = pathbuf.file_name().and_then(|file_name| file_name.to_str());
@@ -56,36 +64,52 @@ impl Tray {
             }
         }
 
-        //outbound
-        let mut outbound_menu: SystemTrayMenu = SystemTrayMenu::new();
-        let select_outbound: Option<String> = IConfig::active_outbound();
-        if let Some(outbound_list) = IConfig::get_outbound_list() {
-            for pathbuf in outbound_list {
-                let file_name = pathbuf.file_name().and_then(|file_name| file_name.to_str());
-                match file_name {
-                    Some(name) => {
-                        let mut is_selected = false;
+        //outbound, 选择了代理链时不选中单个outbound
+        let config = IConfig::snapshot(app);
+        let active_chain = config
+            .as_ref()
+            .and_then(|config| config.active_chain.clone());
+        let select_outbound: Option<String> = match active_chain {
+            Some(_) => None,
+            None => IConfig::active_outbound(app),
+        };
+        let outbound_list = IConfig::get_outbound_list().unwrap_or_default();
+        let mut outbound_menu = Tray::outbound_menu(&outbound_list, 0, select_outbound.as_deref());
 
-                        if let Some(ref select_outbound_name) = select_outbound {
-                            is_selected = select_outbound_name.as_str() == name;
-                        }
-
-                        let item_id = format!("{}{}", "outbound_", name);
-                        let mut item = CustomMenuItem::new(item_id, name);
-                        if is_selected {
-                            item = item.selected()
-                        }
-
-                        outbound_menu = outbound_menu.add_item(item)
-                    }
-                    None => {}
+        //代理链
+        let chains = Chains::list(app);
+        if !chains.is_empty() {
+            outbound_menu = outbound_menu
+                .add_native_item(SystemTrayMenuItem::Separator)
+                .add_item(CustomMenuItem::new("group_chain", t!("Chains", "代理链")).disabled());
+            for chain in chains {
+                let item_id = format!("{}{}", "chain_", chain.name);
+                let label = format!("{} ({} → {})", chain.name, chain.dialer, chain.outbound);
+                let mut item = CustomMenuItem::new(item_id, label);
+                if active_chain.as_deref() == Some(chain.name.as_str()) {
+                    item = item.selected()
                 }
+                outbound_menu = outbound_menu.add_item(item);
+            }
+        }
+
+        //profile
+        let mut profile_menu: SystemTrayMenu = SystemTrayMenu::new();
+        for profile in Profile::list(app) {
+            let item_id = format!("{}{}", "profile_", profile.name);
+            let mut item = CustomMenuItem::new(item_id, profile.name.clone());
+            if config
+                .as_ref()
+                .is_some_and(|config| profile.matches(config))
+            {
+                item = item.selected()
             }
+            profile_menu = profile_menu.add_item(item)
         }
 
         //sys proxy
         let mut sys_port_menu = CustomMenuItem::new("system_proxy", "系统代理");
-        let is_sys_port_select = IConfig::sys_port_enable().unwrap_or(true);
+        let is_sys_port_select = IConfig::sys_port_enable(app).unwrap_or(true);
         if is_sys_port_select {
             sys_port_menu = sys_port_menu.selected()
         }
@@ -94,6 +118,18 @@ impl Tray {
             .add_item(sys_port_menu)
             .add_submenu(SystemTraySubmenu::new("路由切换", router_menu))
             .add_submenu(SystemTraySubmenu::new("outbound切换", outbound_menu))
+            .add_submenu(SystemTraySubmenu::new(
+                t!("Profiles", "配置方案"),
+                profile_menu,
+            ))
+            .add_item(CustomMenuItem::new(
+                "quick_rule_proxy",
+                t!("Proxy Clipboard Domain", "剪贴板域名走代理"),
+            ))
+            .add_item(CustomMenuItem::new(
+                "quick_rule_direct",
+                t!("Direct Clipboard Domain", "剪贴板域名直连"),
+            ))
             .add_native_item(SystemTrayMenuItem::Separator)
             .add_submenu(SystemTraySubmenu::new(
                 t!("Open Dir", "打开目录"),
@@ -109,6 +145,10 @@ impl Tray {
                     .add_item(CustomMenuItem::new(
                         "open_logs_dir",
                         t!("Logs Dir", "日志目录"),
+                    ))
+                    .add_item(CustomMenuItem::new(
+                        "open_user_confdir",
+                        t!("User Confdir", "自定义配置目录"),
                     )),
             ))
             .add_submenu(SystemTraySubmenu::new(
@@ -135,24 +175,98 @@ impl Tray {
         tray_menu
     }
 
+    // outbound 菜单, 每层子目录是一个子菜单
+    // outbounds 需按目录排序, 同一目录下的 outbound 是连续的
+    fn outbound_menu(
+        outbounds: &[OutboundEntry],
+        depth: usize,
+        select_outbound: Option<&str>,
+    ) -> SystemTrayMenu {
+        let mut menu = SystemTrayMenu::new();
+        let mut current_group: Option<&str> = None;
+        let mut index = 0;
+        while index < outbounds.len() {
+            let outbound = &outbounds[index];
+
+            // 子目录
+            if let Some(folder_name) = outbound.folder.get(depth) {
+                let folder_len = outbounds[index..]
+                    .iter()
+                    .take_while(|o| o.folder.get(depth) == Some(folder_name))
+                    .count();
+                let sub_menu = Tray::outbound_menu(
+                    &outbounds[index..index + folder_len],
+                    depth + 1,
+                    select_outbound,
+                );
+                menu = menu.add_submenu(SystemTraySubmenu::new(folder_name.as_str(), sub_menu));
+                index += folder_len;
+                continue;
+            }
+
+            // 同一目录下已按分组排序, 分组变化时加分组标题
+            if outbound.group().is_some() && outbound.group() != current_group {
+                current_group = outbound.group();
+                let group_id = format!(
+                    "group_{}/{}",
+                    outbound.folder.join("/"),
+                    current_group.unwrap_or_default()
+                );
+                menu = menu
+                    .add_native_item(SystemTrayMenuItem::Separator)
+                    .add_item(
+                        CustomMenuItem::new(group_id, current_group.unwrap_or_default())
+                            .disabled(),
+                    );
+            }
+
+            let item_id = format!("{}{}", "outbound_", outbound.id);
+            let mut item = CustomMenuItem::new(item_id, outbound.label());
+            if select_outbound == Some(outbound.id.as_str()) {
+                item = item.selected()
+            }
+            menu = menu.add_item(item);
+            index += 1;
+        }
+        menu
+    }
+
     pub fn update_tray(app_handle: &AppHandle) -> Result<()> {
-        let menu = Tray::menu();
+        let menu = Tray::menu(app_handle);
         app_handle.tray_handle().set_menu(menu)?;
         Ok(())
     }
 
+    // 配置变化时重建菜单
+    pub fn on_config_change(app: &AppHandle, _: &UserConfigValue, _: &UserConfigValue) {
+        log_err!(Tray::update_tray(app));
+    }
+
+    // 剪贴板里的域名加到当前路由的快捷规则
+    fn add_clipboard_domain(app: &AppHandle, outbound_tag: &str) {
+        let content = ClipboardContext::new()
+            .and_then(|mut ctx| ctx.get_contents())
+            .map_err(|err| anyhow::anyhow!("failed to read clipboard: {err}"));
+        log_err!(content
+            .and_then(|content| Routings::add_quick_domain(app, &content, outbound_tag))
+            .map(|domain| log::info!(target: "app", "route {} to {}", domain, outbound_tag)));
+    }
+
     // 菜单事件
     pub fn handler(app: &AppHandle, event: SystemTrayEvent) {
         match event {
             SystemTrayEvent::MenuItemClick { id, .. } => match id.as_str() {
-                "restart_xray" => cmds::restart_xray(),
+                "restart_xray" => cmds::restart_xray(app.clone()),
                 "open_app_dir" => cmds::open_app_home_dir(),
                 "open_core_dir" => cmds::open_core_dir(),
                 "open_logs_dir" => cmds::open_log_dir(),
+                "open_user_confdir" => cmds::open_user_confdir(),
+                "quick_rule_proxy" => Tray::add_clipboard_domain(app, "proxy"),
+                "quick_rule_direct" => Tray::add_clipboard_domain(app, "direct"),
                 "copy_env" => {
                     let mut ctx = ClipboardContext::new().unwrap();
                     // export http_proxy=http://127.0.0.1:10809;export https_proxy=http://127.0.0.1:10809;
-                    let port_config = IConfig::port_config()
+                    let port_config = IConfig::port_config(app)
                         .and_then(|v| v.http_port)
                         .map(|v| v.to_string())
                         .unwrap_or("80".to_string());
@@ -160,13 +274,11 @@ impl Tray {
                     ctx.set_contents(content.into()).unwrap();
                 }
                 "system_proxy" => {
-                    let enable: bool = IConfig::sys_port_enable().unwrap_or(true);
-                    log_err!(IConfig::set_sys_port_enable(!enable));
-                    log_err!(Sysopt::sync_proxy());
-                    log_err!(Tray::update_tray(app));
+                    let enable: bool = IConfig::sys_port_enable(app).unwrap_or(true);
+                    log_err!(IConfig::set_sys_port_enable(app, !enable));
                 }
                 "quit" => {
-                    log_err!(IConfig::write_config());
+                    log_err!(IConfig::write_config(app));
                     log_err!(xray::Xray::kill_old());
                     api::process::kill_children();
                     app.exit(0);
@@ -177,16 +289,25 @@ impl Tray {
                 }
                 s if s.starts_with("router_") => {
                     if let Some(rest_of_string) = s.strip_prefix("router_") {
-                        log_err!(IConfig::set_active_routing(rest_of_string.to_string()));
-                        log_err!(Tray::update_tray(app));
-                        log_err!(xray::Xray::reload_xray());
+                        log_err!(IConfig::set_active_routing(app, rest_of_string.to_string()));
                     }
                 }
                 s if s.starts_with("outbound_") => {
                     if let Some(rest_of_string) = s.strip_prefix("outbound_") {
-                        log_err!(IConfig::set_active_outbound(rest_of_string.to_string()));
-                        log_err!(Tray::update_tray(app));
-                        log_err!(xray::Xray::reload_xray())
+                        log_err!(IConfig::set_active_outbound(
+                            app,
+                            rest_of_string.to_string()
+                        ));
+                    }
+                }
+                s if s.starts_with("chain_") => {
+                    if let Some(rest_of_string) = s.strip_prefix("chain_") {
+                        log_err!(Chains::select(app, rest_of_string));
+                    }
+                }
+                s if s.starts_with("profile_") => {
+                    if let Some(rest_of_string) = s.strip_prefix("profile_") {
+                        log_err!(Profile::apply(app, rest_of_string));
                     }
                 }
                 _ => {}
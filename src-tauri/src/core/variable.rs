@@ -0,0 +1,232 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use tauri::AppHandle;
+
+use super::config::IConfig;
+use super::path::AppPath;
+
+/* 由当前配置决定的变量, 不能自定义 */
+static BUILTIN_VARIABLES: &[&str] = &["HTTP_PORT", "SOCKS_PORT", "ASSET_DIR"];
+
+/* 由入站端口决定的变量, 入站的端口不能引用 */
+static PORT_VARIABLES: &[&str] = &["HTTP_PORT", "SOCKS_PORT"];
+
+/* 没有自定义时 ${LISTEN} 的值, 和预设的入站一致 */
+static DEFAULT_LISTEN: &str = "0.0.0.0";
+
+/* 值是数字的字段, 整个值是一个变量时替换成数字, 如 "port": "${HTTP_PORT}"; 其它字段都替换成字符串 */
+static NUMERIC_KEYS: &[&str] = &[
+    "port",
+    "level",
+    "userLevel",
+    "concurrency",
+    "xudpConcurrency",
+    "mark",
+    "poolSize",
+];
+
+/* 配置文件里的 ${NAME} 变量, 暂存时替换, $${ 表示 ${ 本身 */
+pub struct Variables {}
+
+impl Variables {
+    /* 当前的全部变量 */
+    pub fn list(app: &AppHandle) -> BTreeMap<String, String> {
+        let mut variables = BTreeMap::new();
+        variables.insert("LISTEN".to_string(), DEFAULT_LISTEN.to_string());
+        if let Some(config) = IConfig::snapshot(app) {
+            variables.extend(config.variables);
+        }
+
+        if let Some(port_config) = IConfig::port_config(app) {
+            if let Some(http_port) = port_config.http_port {
+                variables.insert("HTTP_PORT".to_string(), http_port.to_string());
+            }
+            if let Some(socks_port) = port_config.socks_port {
+                variables.insert("SOCKS_PORT".to_string(), socks_port.to_string());
+            }
+        }
+        if let Ok(asset_dir) = AppPath::xray_preset_asset_dir() {
+            variables.insert(
+                "ASSET_DIR".to_string(),
+                asset_dir.to_string_lossy().to_string(),
+            );
+        }
+        variables
+    }
+
+    /* 整体替换自定义变量 */
+    pub fn set(app: &AppHandle, variables: BTreeMap<String, String>) -> Result<()> {
+        for name in variables.keys() {
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+            if !valid {
+                anyhow::bail!("invalid variable name {}, use A-Z, 0-9 and _", name);
+            }
+            if BUILTIN_VARIABLES.contains(&name.as_str()) {
+                anyhow::bail!("variable {} is builtin", name);
+            }
+        }
+        IConfig::update(app, |config| config.variables = variables)
+    }
+
+    /* 替换 json 里所有字符串中的变量 */
+    pub fn substitute(app: &AppHandle, value: &Value) -> Result<Value> {
+        substitute_value(value, None, &Variables::list(app))
+    }
+
+    /* 替换入站的端口, 读出 HTTP_PORT/SOCKS_PORT 之前用, 不能引用这两个变量 */
+    pub fn substitute_port(app: &AppHandle, port: &Value) -> Result<Value> {
+        let mut variables = Variables::list(app);
+        for name in PORT_VARIABLES {
+            if port
+                .as_str()
+                .is_some_and(|text| text.contains(format!("${{{}}}", name).as_str()))
+            {
+                anyhow::bail!("inbound port can not refer to ${{{}}}", name);
+            }
+            variables.remove(*name);
+        }
+        substitute_value(port, Some("port"), &variables)
+    }
+
+    /* 替换暂存目录里所有文件的变量 */
+    pub fn substitute_dir(app: &AppHandle, confdir: &Path) -> Result<()> {
+        let variables = Variables::list(app);
+        for entry in fs::read_dir(confdir)? {
+            let path = entry?.path();
+            if path.extension() != Some("json".as_ref()) {
+                continue;
+            }
+            let json_str = fs::read_to_string(&path)?;
+            if !json_str.contains("${") {
+                continue;
+            }
+            let value: Value = serde_json::from_str(json_str.as_str())
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            let value = substitute_value(&value, None, &variables)
+                .with_context(|| format!("failed to substitute {}", path.display()))?;
+            fs::write(&path, serde_json::to_string_pretty(&value)?)?;
+        }
+        Ok(())
+    }
+}
+
+/* key 是值所在的字段名, 数组里的值没有 */
+fn substitute_value(
+    value: &Value,
+    key: Option<&str>,
+    variables: &BTreeMap<String, String>,
+) -> Result<Value> {
+    Ok(match value {
+        Value::String(text) => {
+            let replaced = substitute_str(text, variables)?;
+            let is_numeric_key = key.is_some_and(|key| NUMERIC_KEYS.contains(&key));
+            let is_whole = text
+                .strip_prefix("${")
+                .and_then(|rest| rest.strip_suffix('}'))
+                .is_some_and(|name| !name.contains(['$', '{', '}']));
+            match replaced.parse::<u64>() {
+                Ok(number) if is_numeric_key && is_whole => Value::from(number),
+                _ => Value::String(replaced),
+            }
+        }
+        Value::Array(list) => Value::Array(
+            list.iter()
+                .map(|item| substitute_value(item, None, variables))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, item)| Ok((key.clone(), substitute_value(item, Some(key), variables)?)))
+                .collect::<Result<_>>()?,
+        ),
+        _ => value.clone(),
+    })
+}
+
+fn substitute_str(text: &str, variables: &BTreeMap<String, String>) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = rest.find('$') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index..];
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            result.push_str("${");
+            rest = escaped;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after
+                .find('}')
+                .ok_or(anyhow::anyhow!("unclosed variable in \"{}\"", text))?;
+            result.push_str(lookup(variables, &after[..end])?);
+            rest = &after[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn lookup<'a>(variables: &'a BTreeMap<String, String>, name: &str) -> Result<&'a String> {
+    variables
+        .get(name)
+        .ok_or(anyhow::anyhow!("undefined variable ${{{}}}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn variables() -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("HTTP_PORT".to_string(), "10809".to_string()),
+            ("LISTEN".to_string(), "127.0.0.1".to_string()),
+        ])
+    }
+
+    #[test]
+    fn substitute_str_escapes_and_plain_dollars() {
+        let variables = variables();
+        assert_eq!(
+            substitute_str("$${HTTP_PORT} is ${HTTP_PORT}", &variables).unwrap(),
+            "${HTTP_PORT} is 10809"
+        );
+        assert_eq!(substitute_str("a$b$", &variables).unwrap(), "a$b$");
+    }
+
+    #[test]
+    fn substitute_str_rejects_unclosed_and_undefined() {
+        let variables = variables();
+        assert!(substitute_str("${HTTP_PORT", &variables).is_err());
+        assert!(substitute_str("${UNKNOWN}", &variables).is_err());
+    }
+
+    #[test]
+    fn substitute_value_only_converts_numeric_keys() {
+        let value = json!({
+            "listen": "${LISTEN}",
+            "port": "${HTTP_PORT}",
+            "tag": "${HTTP_PORT}",
+            "settings": { "address": "${LISTEN}:${HTTP_PORT}", "userLevel": "$${HTTP_PORT}" },
+            "ports": ["${HTTP_PORT}"]
+        });
+        assert_eq!(
+            substitute_value(&value, None, &variables()).unwrap(),
+            json!({
+                "listen": "127.0.0.1",
+                "port": 10809,
+                "tag": "10809",
+                "settings": { "address": "127.0.0.1:10809", "userLevel": "${HTTP_PORT}" },
+                "ports": ["10809"]
+            })
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::Result;
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, DebouncedEvent, Debouncer};
+use once_cell::sync::OnceCell;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::api::notification::Notification;
+use tauri::AppHandle;
+
+use crate::log_err;
+
+use super::config::IConfig;
+use super::outbound::Outbounds;
+use super::path::AppPath;
+use super::tray::Tray;
+use super::xray::Xray;
+
+/* 编辑器保存时的多次写入合并成一次 */
+const DEBOUNCE: Duration = Duration::from_millis(800);
+
+/* watcher 释放后就不再监听, 一直保留到退出 */
+static WATCHER: OnceCell<Mutex<Debouncer<RecommendedWatcher>>> = OnceCell::new();
+
+/* 监听路由和 outbound 目录, 外部修改后不用再手动刷新配置 */
+pub struct ConfigWatcher {}
+
+impl ConfigWatcher {
+    pub fn start(app: &AppHandle) -> Result<()> {
+        let app_handle = app.clone();
+        let mut debouncer =
+            new_debouncer(DEBOUNCE, move |result: DebounceEventResult| match result {
+                Ok(events) => ConfigWatcher::on_change(&app_handle, events),
+                Err(err) => log::error!(target: "app", "[watcher]: {err}"),
+            })?;
+        debouncer
+            .watcher()
+            .watch(&AppPath::xray_routing_dir()?, RecursiveMode::NonRecursive)?;
+        debouncer
+            .watcher()
+            .watch(&AppPath::xray_outbound_dir()?, RecursiveMode::Recursive)?;
+        WATCHER
+            .set(Mutex::new(debouncer))
+            .map_err(|_| anyhow::anyhow!("watcher already started"))?;
+        Ok(())
+    }
+
+    /* 任何变化都重建菜单; 暂存用到的文件修改后重启 xray, 被删除时提示 */
+    fn on_change(app: &AppHandle, events: Vec<DebouncedEvent>) {
+        let paths: Vec<PathBuf> = events
+            .into_iter()
+            .map(|event| event.path)
+            .filter(|path| !is_temp_file(path))
+            .collect();
+        if paths.is_empty() {
+            return;
+        }
+        log_err!(Tray::update_tray(app));
+
+        let mut reload = false;
+        for path in &paths {
+            let Some(label) = ConfigWatcher::staged_label(app, path) else {
+                continue;
+            };
+            if !path.exists() {
+                log::warn!(target: "app", "[watcher]: {label} was deleted");
+                ConfigWatcher::notice(app, &format!("当前使用的 {label} 已被删除"));
+            } else if Xray::changed_since_load(path) {
+                // app 自己写入的文件已经重启过, 修改时间早于暂存时间
+                reload = true;
+            }
+        }
+        if reload {
+            log::info!(target: "app", "[watcher]: staged file changed, reload xray");
+            log_err!(Xray::reload_xray(app));
+        }
+    }
+
+    /* 当前暂存用到的路由或 outbound, 返回提示里的名字 */
+    fn staged_label(app: &AppHandle, path: &Path) -> Option<String> {
+        let routing_dir = AppPath::xray_routing_dir().ok()?;
+        if path.parent() == Some(routing_dir.as_path()) {
+            let name = path.file_name()?.to_str()?;
+            return (IConfig::active_routing(app).as_deref() == Some(name))
+                .then(|| format!("路由 {}", name));
+        }
+
+        let relative = path.strip_prefix(AppPath::xray_outbound_dir().ok()?).ok()?;
+        let id = relative
+            .components()
+            .map(|component| component.as_os_str().to_str())
+            .collect::<Option<Vec<&str>>>()?
+            .join("/");
+        Outbounds::in_use(app, &id).then(|| format!("outbound {}", id))
+    }
+
+    fn notice(app: &AppHandle, body: &str) {
+        log_err!(Notification::new(&app.config().tauri.bundle.identifier)
+            .title(&app.package_info().name)
+            .body(body)
+            .show());
+    }
+}
+
+/* Store::write_atomic 写入时的临时文件 */
+fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|file_name| file_name.to_str())
+        .is_some_and(|file_name| file_name.starts_with('.') && file_name.ends_with(".tmp"))
+}
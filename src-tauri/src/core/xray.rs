@@ -1,10 +1,36 @@
-use std::{fs, io::Write, str::FromStr};
+use std::{fs, io::Write, path::Path, str::FromStr};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
 use anyhow::{Context, Result};
+use serde_json::Value;
 use sysinfo::{Pid, ProcessExt, System, SystemExt};
 use tauri::api::process::{Command, CommandEvent};
+use tauri::AppHandle;
 
-use super::{config::IConfig, path};
+use crate::log_err;
 
+use super::{
+    chain::Chains,
+    check::ConfigCheck,
+    config::{IConfig, UserConfigValue},
+    dns::Dns,
+    format::normalize_dir,
+    loaded::LoadedOutbounds,
+    outbound::Outbounds,
+    overlay::ConfdirOverlay,
+    path,
+    reverse::ReverseProxies,
+    sys::Sysopt,
+    template::RoutingTemplates,
+    variable::Variables,
+};
+
+
+/* 最近一次暂存配置的时间 */
+static LAST_LOAD: Mutex<Option<SystemTime>> = Mutex::new(None);
+
+/* 命令和监听线程可能同时重启, 交错时旧进程关不掉 */
+static RELOAD_LOCK: Mutex<()> = Mutex::new(());
 
 pub struct Xray {}
 
@@ -28,7 +54,8 @@ impl Xray {
         Ok(())
     }
 
-    pub fn load() -> Result<()> {
+    pub fn load(app: &AppHandle) -> Result<()> {
+        *LAST_LOAD.lock().unwrap_or_else(PoisonError::into_inner) = Some(SystemTime::now());
         // `new_sidecar()` expects just the filename, NOT the whole path like in JavaScript
         let cmd = Command::new_sidecar("xray")?;
         let temp_path: std::path::PathBuf = path::AppPath::xray_temp_config_dir()?;
@@ -61,19 +88,64 @@ impl Xray {
         }
         let options = fs_extra::dir::CopyOptions::new().overwrite(true);
         fs_extra::copy_items(&from_paths, confdir, &options)?;
+        //预设转成不带注释的json
+        normalize_dir(&temp_path)?;
+        //用户的配置覆盖预设
+        ConfdirOverlay::apply(&temp_path)?;
+        //用户confdir里改了端口时, 系统代理跟着用新的端口
+        if IConfig::refresh_port_config(app, &temp_path.join("05_inbounds.json"))? {
+            log_err!(Sysopt::sync_proxy(app));
+        }
+        //覆盖入站端口
+        Xray::patch_inbound_ports(app, &temp_path.join("05_inbounds.json"))?;
 
-        //复制outbound
-        let outbound_path = path::AppPath::xray_outbound_dir()
-            .map(|path| path.join(IConfig::active_outbound().unwrap_or_default()))?;
+        //复制outbound, 选择了代理链时合并两个outbound, 都覆盖上outbound的选项
         let outbound_temp_path = temp_path.join("98.outbounds.tail.json");
-        let options = fs_extra::file::CopyOptions::new();
-        fs_extra::file::copy(outbound_path, outbound_temp_path, &options)?;
-        //复制路由
+        if let Some(chain) = Chains::active(app) {
+            let outbounds = Chains::render(&chain)?;
+            fs::write(
+                &outbound_temp_path,
+                serde_json::to_string_pretty(&outbounds)?,
+            )?;
+        } else {
+            let active_outbound = IConfig::active_outbound(app).unwrap_or_default();
+            if !Outbounds::resolve(&active_outbound)?.is_file() {
+                anyhow::bail!("active outbound {} not found", active_outbound);
+            }
+            //覆盖outbound的选项, 原文件不改
+            let outbound = Outbounds::read_staged(&active_outbound)?;
+            fs::write(
+                &outbound_temp_path,
+                serde_json::to_string_pretty(&outbound)?,
+            )?;
+        }
+        //复制路由, 去掉禁用的规则, 拼上选择的规则块
         let router_path = path::AppPath::xray_routing_dir()
-            .map(|path| path.join(IConfig::active_routing().unwrap_or_default()))?;
+            .map(|path| path.join(IConfig::active_routing(app).unwrap_or_default()))?;
         let router_temp_path = temp_path.join("99.routing.json");
-        let options = fs_extra::file::CopyOptions::new();
-        fs_extra::file::copy(router_path, router_temp_path, &options)?;
+        let mut routing = RoutingTemplates::render(app, &router_path)?;
+        //反向代理, 规则放在最前面
+        ReverseProxies::stage(app, &temp_path, &mut routing)?;
+        //dns, 劫持dns请求的规则放在最前面
+        Dns::stage(app, &temp_path, &mut routing)?;
+        fs::write(router_temp_path, serde_json::to_string_pretty(&routing)?)?;
+        //加载的outbound用各自的tag合并, 排在当前outbound前面, 最后暂存才能检查所有出站的tag
+        if let Some(loaded) = LoadedOutbounds::render(&LoadedOutbounds::list(app), &temp_path)? {
+            fs::write(
+                temp_path.join("97.outbounds.loaded.tail.json"),
+                serde_json::to_string_pretty(&loaded)?,
+            )?;
+        }
+        //替换所有暂存文件里的变量
+        Variables::substitute_dir(app, &temp_path)?;
+
+        //检查路由引用的tag是否存在
+        match ConfigCheck::check_routing(app, &temp_path, &router_path) {
+            Ok(dangling) => dangling
+                .iter()
+                .for_each(|dangling| log::warn!(target: "app", "[check]: {dangling}")),
+            Err(err) => log::error!(target: "app", "[check]: {err}"),
+        }
 
         //运行
         // see https://xtls.github.io/config/features/env.html
@@ -121,16 +193,80 @@ impl Xray {
         Ok(())
     }
 
-    pub fn reload_xray() -> Result<()> {
+    /* 只写用户设置的端口, 没有设置时用 confdir 里的 */
+    fn patch_inbound_ports(app: &AppHandle, inbounds_path: &Path) -> Result<()> {
+        let config = IConfig::snapshot(app).ok_or(anyhow::anyhow!("failed to get config"))?;
+        if config.http_port.is_none() && config.socks_port.is_none() {
+            return Ok(());
+        }
+        let json_str = fs::read_to_string(inbounds_path)?;
+        let mut inbounds_config: Value = serde_json::from_str(json_str.as_str())?;
+
+        if let Some(inbounds) = inbounds_config
+            .get_mut("inbounds")
+            .and_then(|inbounds| inbounds.as_array_mut())
+        {
+            for inbound in inbounds {
+                let port = match inbound.get("protocol").and_then(|protocol| protocol.as_str()) {
+                    Some("http") => config.http_port,
+                    Some("socks") => config.socks_port,
+                    _ => None,
+                };
+                if let Some(port) = port {
+                    inbound["port"] = Value::from(port);
+                }
+            }
+        }
+
+        fs::write(inbounds_path, serde_json::to_string_pretty(&inbounds_config)?)?;
+        Ok(())
+    }
+
+    /* 文件在最近一次暂存之后修改过 */
+    pub fn changed_since_load(path: &Path) -> bool {
+        let last_load = *LAST_LOAD.lock().unwrap_or_else(PoisonError::into_inner);
+        let modified = fs::metadata(path).and_then(|metadata| metadata.modified());
+        match (last_load, modified) {
+            (Some(last_load), Ok(modified)) => modified > last_load,
+            _ => true,
+        }
+    }
+
+    pub fn reload_xray(app: &AppHandle) -> Result<()> {
+        let _guard = RELOAD_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
         log::debug!("reload_xray kill");
         //关闭
         Xray::kill_old()?;
 
         log::debug!("reload_xray load");
         //启动
-        Xray::load()?;
+        Xray::load(app)?;
 
         log::debug!("reload_xray end");
         Ok(())
     }
+
+    // 路由/outbound/端口/规则块/代理链/加载的outbound/反向代理/dns/变量变化时重启xray
+    pub fn on_config_change(
+        app: &AppHandle,
+        old_config: &UserConfigValue,
+        new_config: &UserConfigValue,
+    ) {
+        if old_config.active_routing != new_config.active_routing
+            || old_config.active_outbound != new_config.active_outbound
+            || old_config.http_port != new_config.http_port
+            || old_config.socks_port != new_config.socks_port
+            || old_config.routing_blocks != new_config.routing_blocks
+            || old_config.routing_block_position != new_config.routing_block_position
+            || old_config.active_chain != new_config.active_chain
+            || (new_config.active_chain.is_some() && old_config.chains != new_config.chains)
+            || old_config.loaded_outbounds != new_config.loaded_outbounds
+            || old_config.reverse_bridges != new_config.reverse_bridges
+            || old_config.dns != new_config.dns
+            || old_config.variables != new_config.variables
+        {
+            log_err!(Xray::reload_xray(app));
+        }
+    }
 }
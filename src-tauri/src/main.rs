@@ -12,7 +12,7 @@ use tauri::{App, Manager, SystemTray};
 use tauri_plugin_autostart::MacosLauncher;
 use tauri_plugin_log::{
     fern::colors::{Color, ColoredLevelConfig},
-    LogTarget,
+    LogTarget, RotationStrategy,
 };
 
 use crate::core::config::IConfig;
@@ -27,11 +27,18 @@ struct Payload {
 
 fn main() {
     // 初始化日志
+    // 超过大小的日志在启动时轮转保留, 旧日志由 setup_app 里的清理处理
+    // 此时日志还没初始化, 解析失败的配置留给 init_config 处理
+    let log_config = IConfig::read_user_config()
+        .map(|config| config.log)
+        .unwrap_or_default();
     let mut log = tauri_plugin_log::Builder::default()
         .targets([
             LogTarget::Folder(core::path::AppPath::app_log_dir().unwrap()),
             LogTarget::Stdout,
         ])
+        .max_file_size(log_config.max_file_size as u128)
+        .rotation_strategy(RotationStrategy::KeepAll)
         .level(log::LevelFilter::Debug);
 
     if cfg!(debug_assertions) {
@@ -58,7 +65,71 @@ fn main() {
         ))
         .system_tray(SystemTray::new())
         .on_system_tray_event(core::tray::Tray::handler)
-        .invoke_handler(tauri::generate_handler![cmds::greet,])
+        .invoke_handler(tauri::generate_handler![
+            cmds::greet,
+            cmds::get_user_config,
+            cmds::list_profiles,
+            cmds::save_profile,
+            cmds::delete_profile,
+            cmds::apply_profile,
+            cmds::list_outbounds,
+            cmds::set_outbound_meta,
+            cmds::get_outbound_options,
+            cmds::set_outbound_options,
+            cmds::read_outbound,
+            cmds::create_outbound,
+            cmds::replace_outbound,
+            cmds::rename_outbound,
+            cmds::duplicate_outbound,
+            cmds::delete_outbound,
+            cmds::create_routing,
+            cmds::rename_routing,
+            cmds::delete_routing,
+            cmds::list_routing_rules,
+            cmds::insert_routing_rule,
+            cmds::update_routing_rule,
+            cmds::move_routing_rule,
+            cmds::set_routing_rule_enabled,
+            cmds::delete_routing_rule,
+            cmds::check_active_routing,
+            cmds::simulate_route,
+            cmds::list_geo_categories,
+            cmds::list_geosite_domains,
+            cmds::list_geoip_cidrs,
+            cmds::find_geosite,
+            cmds::find_geoip,
+            cmds::import_domain_list,
+            cmds::list_domain_lists,
+            cmds::save_domain_list,
+            cmds::delete_domain_list,
+            cmds::refresh_domain_list,
+            cmds::list_routing_blocks,
+            cmds::set_routing_blocks,
+            cmds::set_routing_block_position,
+            cmds::save_routing_block,
+            cmds::delete_routing_block,
+            cmds::list_chains,
+            cmds::save_chain,
+            cmds::delete_chain,
+            cmds::select_chain,
+            cmds::list_loaded_outbounds,
+            cmds::set_loaded_outbounds,
+            cmds::list_reverse_bridges,
+            cmds::save_reverse_bridge,
+            cmds::delete_reverse_bridge,
+            cmds::generate_reverse,
+            cmds::generate_server_config,
+            cmds::rotate_server_keys,
+            cmds::create_server_node,
+            cmds::read_server_config,
+            cmds::generate_uuid,
+            cmds::generate_x25519,
+            cmds::generate_short_id,
+            cmds::get_dns_settings,
+            cmds::set_dns_settings,
+            cmds::list_variables,
+            cmds::set_variables,
+        ])
         .setup(|app: &mut App| {
             setup_app(app);
             Ok(())
@@ -96,14 +167,38 @@ fn setup_app(app: &mut App) {
     // 初始化文件目录
     log_err!(core::path::AppPath::init_path(app.path_resolver()));
 
-    // 初始化配置
-    log_err!(IConfig::init_config());
+    // 初始化配置, 之后通过 app handle 取到配置
+    let app_handle = app.app_handle();
+    log_err!(IConfig::init_config(&app_handle));
+
+    // 清理旧日志
+    log_err!(core::logs::Logs::cleanup(
+        &app_handle,
+        &app.package_info().name
+    ));
 
     // 初始化的时候先同步下系统配置
-    log_err!(Sysopt::sync_proxy());
+    log_err!(Sysopt::sync_proxy(&app_handle));
 
     // 初始化xray进程
-    log_err!(core::xray::Xray::reload_xray());
+    log_err!(core::xray::Xray::reload_xray(&app_handle));
+
+    // 配置变化时同步托盘/系统代理/xray, 并通知前端
+    log_err!(IConfig::subscribe(&app_handle, Tray::on_config_change));
+    log_err!(IConfig::subscribe(&app_handle, Sysopt::on_config_change));
+    log_err!(IConfig::subscribe(
+        &app_handle,
+        core::xray::Xray::on_config_change
+    ));
+    log_err!(IConfig::subscribe(&app_handle, |app, _, new_config| {
+        log_err!(app.emit_all("config-changed", new_config.clone()));
+    }));
+
+    // 路由/outbound 目录的文件变化时刷新托盘, 重启 xray
+    log_err!(core::watcher::ConfigWatcher::start(&app_handle));
+
+    // 定时更新订阅的域名列表
+    core::domain_list::DomainLists::start_scheduler(&app_handle);
 
     // 初始化tray
     // 设置没有菜单栏，只有系统托盘图标